@@ -0,0 +1,115 @@
+//! Backgrounds generated for boards that don't have a background image, or drawn behind one.
+
+use ril::{Image, Rgba};
+
+use crate::{palette::TRANSPARENT, tile::paste_cropped};
+
+/// A background, with its colors parsed and its texture loaded
+pub enum Background {
+    Color(Rgba),
+    /// Fades from `from` to `to` along `angle`, in degrees clockwise from left to right
+    LinearGradient {
+        from: Rgba,
+        to: Rgba,
+        angle: f32,
+    },
+    /// Fades from `inner` at the center to `outer` at the corners
+    RadialGradient {
+        inner: Rgba,
+        outer: Rgba,
+    },
+    /// Repeated from the top left corner
+    Texture(Image<Rgba>),
+}
+
+impl Background {
+    /// Draws the background at the size of the board
+    pub fn render(&self, width: u32, height: u32) -> Image<Rgba> {
+        match self {
+            Background::Color(color) => Image::new(width, height, *color),
+            Background::LinearGradient { from, to, angle } => {
+                let (dy, dx) = angle.to_radians().sin_cos();
+                // half the length of the gradient, so that it reaches the corners exactly
+                let half = (width as f32 * dx.abs() + height as f32 * dy.abs()) / 2.0;
+                let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+                Image::from_fn(width, height, |x, y| {
+                    let along = (x as f32 + 0.5 - cx) * dx + (y as f32 + 0.5 - cy) * dy;
+                    mix(*from, *to, 0.5 + along / half.max(1.0) / 2.0)
+                })
+            }
+            Background::RadialGradient { inner, outer } => {
+                let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+                let radius = f32::hypot(cx, cy).max(1.0);
+                Image::from_fn(width, height, |x, y| {
+                    let distance = f32::hypot(x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                    mix(*inner, *outer, distance / radius)
+                })
+            }
+            Background::Texture(texture) => Image::from_fn(width, height, |x, y| {
+                *texture.pixel(x % texture.width(), y % texture.height())
+            }),
+        }
+    }
+}
+
+/// The canvas a board is drawn on: `background` if there is one, with `image` over it,
+/// cropped to `width` by `height`.
+pub fn compose(
+    background: Option<&Background>,
+    image: Option<Image<Rgba>>,
+    width: u32,
+    height: u32,
+) -> Image<Rgba> {
+    let image = match image {
+        Some(image) if background.is_none() && image.dimensions() == (width, height) => {
+            return image
+        }
+        image => image,
+    };
+    let mut canvas = match background {
+        Some(background) => background.render(width, height),
+        None => Image::new(width, height, TRANSPARENT),
+    };
+    if let Some(image) = image {
+        paste_cropped(&mut canvas, image, (0, 0), width, height);
+    }
+    canvas
+}
+
+/// Blends from `a` to `b` by `t`, which is clamped to 0.0 to 1.0.
+fn mix(a: Rgba, b: Rgba, t: f32) -> Rgba {
+    let t = t.clamp(0.0, 1.0);
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    Rgba {
+        r: channel(a.r, b.r),
+        g: channel(a.g, b.g),
+        b: channel(a.b, b.b),
+        a: channel(a.a, b.a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::{BLACK, GREEN, RED};
+
+    #[test]
+    fn it_spans_gradients_across_the_canvas() {
+        let gradient = Background::LinearGradient {
+            from: BLACK,
+            to: RED,
+            angle: 0.0,
+        };
+        let image = gradient.render(100, 10);
+        assert!(image.pixel(0, 5).r < 3);
+        assert!(image.pixel(99, 5).r > 252);
+        assert_eq!(image.pixel(30, 0), image.pixel(30, 9));
+
+        let gradient = Background::RadialGradient {
+            inner: GREEN,
+            outer: BLACK,
+        };
+        let image = gradient.render(10, 10);
+        assert!(image.pixel(5, 5).g > image.pixel(0, 0).g);
+    }
+}
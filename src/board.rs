@@ -1,10 +1,18 @@
-use ril::{Image, OverlayMode, Paste, Rgba};
+//! A built [`Board`], ready to render, and the [`BoardRenderer`] that draws it.
+
+use rayon::prelude::*;
+use ril::{Image, OverlayMode, Paste, Polygon, Rgba};
+use serde::Deserialize;
 
 use crate::{
+    captions::{CaptionContext, Captions},
     palette::TRANSPARENT,
+    progress::TeamProgress,
+    scoring::{Line, Score, ScoringOptions},
     tile::{Tile, TileRenderOptions, TileRenderer},
 };
 
+/// A validated board with all of its images loaded, ready to render
 pub struct Board {
     pub rows: usize,
     pub cols: usize,
@@ -12,20 +20,122 @@ pub struct Board {
     pub content_rect: (u32, u32, u32, u32),
     /// how long or wide a tile is
     pub tile_size: u32,
+    /// how leftover space in the content rect is spread around the tiles
+    pub spacing: Spacing,
     pub tile_render_options: TileRenderOptions,
     pub tiles: Vec<Tile>,
     pub image: Image<Rgba>,
+    pub scoring: ScoringOptions,
+    /// when set, completed lines are struck through
+    pub line_highlight: Option<LineHighlight>,
+    /// text around the tiles, outside the content rect
+    pub captions: Captions,
+    /// the team the board is rendered for, from its progress file
+    pub team: Option<String>,
+}
+
+/// Where the space the tiles don't take up goes, along each axis of the content rect
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Spacing {
+    /// After each tile, so the first tile touches the top left edges and whatever doesn't
+    /// divide evenly is left over at the end
+    #[default]
+    After,
+    /// Between tiles only, the outer tiles touch the edges
+    Between,
+    /// Around each tile, so the edges get half the gap that's between tiles
+    Around,
+    /// Evenly between tiles and the edges
+    Evenly,
+}
+
+impl Spacing {
+    /// How many gaps a line of `count` tiles has, counting the two half gaps at the edges of
+    /// `around` as one.
+    pub fn gaps(&self, count: u32) -> u32 {
+        match self {
+            Spacing::Between => count.saturating_sub(1),
+            Spacing::After | Spacing::Around => count,
+            Spacing::Evenly => count.saturating_add(1),
+        }
+    }
+
+    /// Lays out `count` tiles of `tile_size` along `available` pixels, returning the offset of
+    /// the first tile and the distance from the start of one tile to the next. Pixels that
+    /// don't divide evenly are split between the edges, so the tiles stay centered, except with
+    /// `after`.
+    pub fn spread(&self, count: u32, tile_size: u32, available: u32) -> (u32, u32) {
+        let count = count.max(1);
+        let free = available.saturating_sub(count.saturating_mul(tile_size));
+        let (gap, edge) = match self {
+            Spacing::After => return (0, tile_size.saturating_add(free / count)),
+            // a single tile is centered
+            Spacing::Between if count == 1 => (0, free / 2),
+            Spacing::Between => (free / (count - 1), 0),
+            Spacing::Around => (free / count, free / count / 2),
+            Spacing::Evenly => {
+                let gap = free / count.saturating_add(1);
+                (gap, gap)
+            }
+        };
+        let remainder = free - gap * (count - 1) - edge * 2;
+        (edge + remainder / 2, tile_size.saturating_add(gap))
+    }
 }
 
+/// The stroke drawn through completed lines
+#[derive(Debug)]
+pub struct LineHighlight {
+    pub color: Rgba,
+    /// thickness of the stroke in pixels
+    pub width: u32,
+}
+
+impl Board {
+    /// Replaces the state of every tile with a team's progress, so that one board can be
+    /// rendered for several teams without reloading any images.
+    pub fn apply_progress(&mut self, progress: &TeamProgress) {
+        for tile in self.tiles.iter_mut() {
+            tile.state = progress.state(tile.number);
+            tile.progress = progress.progress(tile.number);
+        }
+        self.team = progress.team.clone();
+    }
+
+    /// Top-left corner of every tile, in the same order as `tiles`.
+    pub fn tile_positions(&self) -> Vec<(u32, u32)> {
+        let (x1, y1, x2, y2) = self.content_rect;
+        let (x_offset, x_step) =
+            self.spacing
+                .spread(self.cols as u32, self.tile_size, x2.saturating_sub(x1));
+        let (y_offset, y_step) =
+            self.spacing
+                .spread(self.rows as u32, self.tile_size, y2.saturating_sub(y1));
+        (0..self.tiles.len())
+            .map(|i| {
+                let col = (i % self.cols) as u32;
+                let row = (i / self.cols) as u32;
+                (x1 + x_offset + col * x_step, y1 + y_offset + row * y_step)
+            })
+            .collect()
+    }
+}
+
+/// Draws a board's tiles over its background image
 pub struct BoardRenderer<'a> {
     tile_renderer: &'a TileRenderer<'a>,
 }
 
 impl<'a> BoardRenderer<'a> {
+    /// A renderer that draws every tile with `tile_renderer`.
     pub fn new(tile_renderer: &'a TileRenderer) -> Self {
         Self { tile_renderer }
     }
 
+    /// Draws `board` at the size of its background: the background, then its tiles (rendered
+    /// in parallel), highlighted lines and captions. Everything that can fail has already been
+    /// checked when the board was built, so this can't.
     pub fn render(&self, board: &Board) -> Image<Rgba> {
         let mut image = Image::new(board.image.width(), board.image.height(), TRANSPARENT);
         // start by compositing the board image onto the output image
@@ -35,37 +145,120 @@ impl<'a> BoardRenderer<'a> {
             mask: None,
             overlay: Some(OverlayMode::Merge),
         });
-        // now compute the spacings for tiles
-        let (x1, y1, x2, y2) = board.content_rect;
-        let content_width = x2 - x1;
-        let content_height = y2 - y1;
-        let cols = board.cols as u32;
-        let rows = board.rows as u32;
-        let tile_size = board.tile_size;
-        let tiles_width = cols * tile_size;
-        let tiles_height = rows * tile_size;
-        let x_pad = (content_width - tiles_width) / cols;
-        let y_pad = (content_height - tiles_height) / rows;
-        // place tiles
-        let mut x = x1;
-        let mut y = y1;
-        for row in board.tiles.chunks(board.cols) {
-            for tile in row {
-                // TODO: customizable theme
-                let tile_image =
-                    self.tile_renderer
-                        .render(tile, board.tile_size, &board.tile_render_options);
-                image.draw(&Paste {
-                    position: (x, y),
-                    image: &tile_image,
-                    mask: None,
-                    overlay: Some(OverlayMode::Merge),
-                });
-                x += tile_size + x_pad;
+        // render tiles in parallel, but place them in order so the output doesn't depend on
+        // which tile finished first
+        let tile_images = board
+            .tiles
+            .par_iter()
+            .map(|tile| {
+                self.tile_renderer
+                    .render(tile, board.tile_size, &board.tile_render_options)
+            })
+            .collect::<Vec<_>>();
+        let positions = board.tile_positions();
+        for (tile_image, &position) in tile_images.iter().zip(positions.iter()) {
+            image.draw(&Paste {
+                position,
+                image: tile_image,
+                mask: None,
+                overlay: Some(OverlayMode::Merge),
+            });
+        }
+        if let Some(highlight) = &board.line_highlight {
+            let score = Score::for_board(board);
+            for line in score.lines.iter() {
+                draw_line_highlight(&mut image, board, &positions, line, highlight);
             }
-            x = x1;
-            y += tile_size + y_pad;
+        }
+        if !board.captions.is_empty() {
+            let states = board
+                .tiles
+                .iter()
+                .map(|tile| tile.state)
+                .collect::<Vec<_>>();
+            let context = CaptionContext {
+                dimensions: image.dimensions(),
+                content_rect: board.content_rect,
+                themes: &board.tile_render_options.themes,
+                states: &states,
+                team: board.team.as_deref(),
+            };
+            let text_renderer = self.tile_renderer.text_renderer();
+            board.captions.draw(&mut image, text_renderer, &context);
         }
         image
     }
 }
+
+/// Strikes through a completed line, from just inside the first tile to just inside the last.
+fn draw_line_highlight(
+    image: &mut Image<Rgba>,
+    board: &Board,
+    positions: &[(u32, u32)],
+    line: &Line,
+    highlight: &LineHighlight,
+) {
+    let indices = line.tile_indices(board.rows, board.cols);
+    let (Some(&first), Some(&last)) = (indices.first(), indices.last()) else {
+        return;
+    };
+    let half_tile = board.tile_size as f32 / 2.0;
+    let center = |i: usize| {
+        let (x, y) = positions[i];
+        (x as f32 + half_tile, y as f32 + half_tile)
+    };
+    let (dx, dy) = match line {
+        Line::Row(_) => (1.0, 0.0),
+        Line::Column(_) => (0.0, 1.0),
+        Line::Diagonal => (1.0, 1.0),
+        Line::AntiDiagonal => (-1.0, 1.0),
+    };
+    let length = f32::hypot(dx, dy);
+    let (dx, dy) = (dx / length, dy / length);
+    // overshoot the tile centers so that the stroke covers most of the end tiles too
+    let overshoot = half_tile * 0.8;
+    let (start_x, start_y) = center(first);
+    let (end_x, end_y) = center(last);
+    let (start_x, start_y) = (start_x - dx * overshoot, start_y - dy * overshoot);
+    let (end_x, end_y) = (end_x + dx * overshoot, end_y + dy * overshoot);
+    // offset perpendicular to the line by half the stroke width on either side
+    let (nx, ny) = (
+        -dy * highlight.width as f32 / 2.0,
+        dx * highlight.width as f32 / 2.0,
+    );
+    let vertex = |x: f32, y: f32| (x.round().max(0.0) as u32, y.round().max(0.0) as u32);
+    let stroke = Polygon::from_vertices([
+        vertex(start_x + nx, start_y + ny),
+        vertex(end_x + nx, end_y + ny),
+        vertex(end_x - nx, end_y - ny),
+        vertex(start_x - nx, start_y - ny),
+    ])
+    .with_fill(highlight.color)
+    .with_overlay_mode(OverlayMode::Merge);
+    image.draw(&stroke);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_spreads_tiles() {
+        // 3 tiles of 100px in 400px leaves 100px over
+        assert_eq!(Spacing::After.spread(3, 100, 400), (0, 133));
+        assert_eq!(Spacing::Between.spread(3, 100, 400), (0, 150));
+        assert_eq!(Spacing::Around.spread(3, 100, 400), (17, 133));
+        assert_eq!(Spacing::Evenly.spread(3, 100, 400), (25, 125));
+        // the last tile ends as far from the edge as the first starts
+        for spacing in [Spacing::Between, Spacing::Around, Spacing::Evenly] {
+            let (offset, step) = spacing.spread(3, 100, 400);
+            assert!((400 - (offset + 2 * step + 100)).abs_diff(offset) <= 1);
+        }
+        assert_eq!(Spacing::Between.spread(1, 100, 151), (25, 100));
+        // absurd sizes leave nothing over rather than overflowing
+        assert_eq!(
+            Spacing::Evenly.spread(u32::MAX, u32::MAX, 400),
+            (0, u32::MAX)
+        );
+    }
+}
@@ -1,47 +1,211 @@
 //! This module contains (de)serializable primitives that can be turned into boards.
 
-use std::collections::HashSet;
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+};
 
 use crate::{
-    board::Board,
-    error::AppError,
-    images::ImageLoader,
-    tile::{Tile, TileRenderOptions},
+    background::{self, Background},
+    board::{Board, LineHighlight, Spacing},
+    captions::{Caption, CaptionContext, Captions, Legend, Region, MAX_TEXT_SIZE},
+    diagnostics::Diagnostic,
+    error::{Axis, Error},
+    images::{self, ImageLoader},
+    palette::{GRAY, ORANGE, YELLOW},
+    scoring::ScoringOptions,
+    text::{TextRenderOptions, TextRenderer},
+    tile::{
+        compute_content_bounds, ImageFit, ImageLayout, ImageOptions, ImageTreatment, ProgressCount,
+        ResizeFilter, Tile, TileRenderOptions, TileState, TileTheme, TileThemes,
+    },
 };
+use ril::{Image, Rgba};
 use serde::Deserialize;
-use thiserror::Error;
-
-#[derive(Debug, Error)]
-pub enum BoardBuilderError {
-    #[error(transparent)]
-    Wrapped(AppError),
 
-    #[error("wrong number of tiles: expected {expected:?}, actual {actual:?}")]
-    WrongNumberOfTiles { expected: usize, actual: usize },
+/// A problem found while validating, along with the path of the YAML value that caused it.
+struct Problem {
+    path: String,
+    error: Error,
+}
 
-    #[error("tiles must be consecutively numbered, missing {0:?}")]
-    MissingTiles(HashSet<u8>),
+impl Problem {
+    fn new(path: impl Into<String>, error: Error) -> Self {
+        Self {
+            path: path.into(),
+            error,
+        }
+    }
+}
 
-    #[error("tiles must be consecutively numbered, unexpected {0:?}")]
-    UnexpectedTiles(HashSet<u8>),
+impl From<Problem> for Diagnostic {
+    fn from(problem: Problem) -> Self {
+        Diagnostic::from_error(&problem.error).at(problem.path)
+    }
+}
 
-    #[error("invalid dimensions: {width:?}px x {height:?}px image cannot support content rectangle {content_rect:?}")]
-    InvalidDimensions {
-        width: u32,
-        height: u32,
-        content_rect: ContentRect,
-    },
+/// Converts a list of problems into the first error, if there is one.
+fn first_error(problems: Vec<Problem>) -> Result<(), Error> {
+    match problems.into_iter().next() {
+        Some(problem) => Err(problem.error),
+        None => Ok(()),
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TileBuilder {
     pub number: u8,
     pub name: String,
-    pub image: String,
-    pub unlocked: bool,
+    /// A path or URL, see `ImageLoader::load`
+    #[serde(default)]
+    pub image: Option<String>,
+    /// The name of an item whose image is looked up on the wiki, instead of giving `image`
+    #[serde(default, alias = "wiki")]
+    pub item: Option<String>,
+    /// Several images shown side by side, for tiles like "any godsword hilt"
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// Several items, shown like `images`
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Overridden when a team's progress file is applied to the board
+    #[serde(default)]
+    pub state: Option<TileState>,
+    /// Legacy alternative to `state`, `true` is completed and `false` is revealed
+    #[serde(default)]
+    pub unlocked: Option<bool>,
+    /// Partial progress, drawn as a bar with a counter
+    #[serde(default)]
+    pub progress: Option<ProgressCount>,
+    /// Overrides `tile_render_options.image_options` for this tile
+    #[serde(default)]
+    pub image_options: Option<ImageOptionsBuilder>,
+}
+
+impl TileBuilder {
+    /// The starting state of the tile, `state` wins over `unlocked` if both are set.
+    pub fn state(&self) -> TileState {
+        match (self.state, self.unlocked) {
+            (Some(state), _) => state,
+            (None, Some(unlocked)) => TileState::from_unlocked(unlocked),
+            (None, None) => TileState::default(),
+        }
+    }
+
+    /// Where each of the tile's images is loaded from, in the order they're drawn, along with
+    /// the field it was given in. Items become `wiki:<item>`.
+    pub fn image_locations(&self) -> Vec<(String, Cow<'_, str>)> {
+        let item = |item| Cow::Owned(format!("wiki:{}", item));
+        let mut locations = Vec::new();
+        if let Some(image) = &self.image {
+            locations.push(("image".to_string(), Cow::Borrowed(image.as_str())));
+        }
+        for (i, image) in self.images.iter().enumerate() {
+            locations.push((format!("images[{}]", i), Cow::Borrowed(image.as_str())));
+        }
+        if let Some(name) = &self.item {
+            locations.push(("item".to_string(), item(name)));
+        }
+        for (i, name) in self.items.iter().enumerate() {
+            locations.push((format!("items[{}]", i), item(name)));
+        }
+        locations
+    }
+}
+
+/// How tile images are fitted onto tiles, anything that isn't given keeps its default
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ImageOptionsBuilder {
+    #[serde(default)]
+    pub trim: Option<bool>,
+    #[serde(default)]
+    pub padding: Option<u32>,
+    #[serde(default)]
+    pub layout: Option<ImageLayout>,
+    #[serde(default)]
+    pub gap: Option<u32>,
+    #[serde(default)]
+    pub fit: Option<ImageFit>,
+    #[serde(default)]
+    pub upscale: Option<bool>,
+    #[serde(default)]
+    pub resize: Option<ResizeFilter>,
+    #[serde(default)]
+    pub max_scale: Option<f32>,
+}
+
+impl ImageOptionsBuilder {
+    /// These options with every field that `overrides` gives replaced.
+    pub fn merge(&self, overrides: &ImageOptionsBuilder) -> ImageOptionsBuilder {
+        ImageOptionsBuilder {
+            trim: overrides.trim.or(self.trim),
+            padding: overrides.padding.or(self.padding),
+            layout: overrides.layout.or(self.layout),
+            gap: overrides.gap.or(self.gap),
+            fit: overrides.fit.or(self.fit),
+            upscale: overrides.upscale.or(self.upscale),
+            resize: overrides.resize.or(self.resize),
+            max_scale: overrides.max_scale.or(self.max_scale),
+        }
+    }
+
+    pub fn build(&self) -> ImageOptions {
+        let defaults = ImageOptions::default();
+        ImageOptions {
+            trim: self.trim.unwrap_or(defaults.trim),
+            padding: self.padding.unwrap_or(defaults.padding),
+            layout: self.layout.unwrap_or(defaults.layout),
+            gap: self.gap.unwrap_or(defaults.gap),
+            fit: self.fit.unwrap_or(defaults.fit),
+            upscale: self.upscale.unwrap_or(defaults.upscale),
+            resize: self.resize.unwrap_or(defaults.resize),
+            max_scale: self.max_scale.or(defaults.max_scale),
+        }
+    }
+
+    /// `path` is where the options are in the YAML, for error reporting.
+    fn validate(&self, path: &str) -> Vec<Problem> {
+        match self.max_scale {
+            Some(value) if value.is_nan() || value <= 0.0 => vec![Problem::new(
+                format!("{}.max_scale", path),
+                Error::InvalidMaxScale { value },
+            )],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// `tile_size`, in pixels or `auto` for the largest that fits in `content_rect`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "TileSizeShim")]
+pub enum TileSize {
+    Fixed(u32),
+    Auto,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TileSizeShim {
+    Fixed(u32),
+    Keyword(String),
+}
+
+impl TryFrom<TileSizeShim> for TileSize {
+    type Error = String;
+
+    fn try_from(shim: TileSizeShim) -> Result<Self, Self::Error> {
+        match shim {
+            TileSizeShim::Fixed(size) => Ok(TileSize::Fixed(size)),
+            TileSizeShim::Keyword(keyword) if keyword == "auto" => Ok(TileSize::Auto),
+            TileSizeShim::Keyword(keyword) => Err(format!(
+                "invalid tile_size {:?}, expected a size in pixels or auto",
+                keyword
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
 pub struct ContentRect {
     pub x1: u32,
     pub y1: u32,
@@ -49,150 +213,1505 @@ pub struct ContentRect {
     pub y2: u32,
 }
 
+impl ContentRect {
+    /// The whole of a board of `dimensions`, less `margin` on every side.
+    pub fn with_margin(dimensions: (u32, u32), margin: u32) -> Self {
+        let (width, height) = dimensions;
+        Self {
+            x1: margin,
+            y1: margin,
+            x2: width.saturating_sub(margin),
+            y2: height.saturating_sub(margin),
+        }
+    }
+
+    /// (x1, y1, x2, y2), the way `Board` keeps it.
+    pub fn bounds(&self) -> (u32, u32, u32, u32) {
+        (self.x1, self.y1, self.x2, self.y2)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TileThemeBuilder {
+    pub border_color: String,
+    pub inset_color: String,
+    pub background_color: String,
+    pub text_color: String,
+    /// Defaults to `text_color`
+    #[serde(default)]
+    pub progress_bar_color: Option<String>,
+    /// Defaults to `inset_color`
+    #[serde(default)]
+    pub progress_track_color: Option<String>,
+    /// Defaults to the treatment for the state the theme is used for
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub image_treatment: Option<ImageTreatment>,
+}
+
+impl TileThemeBuilder {
+    /// Parses every color in the theme, `theme` is the name of the theme for error reporting.
+    pub fn build(&self, theme: &'static str, state: TileState) -> Result<TileTheme, Error> {
+        let border_color = parse_color(theme, "border_color", &self.border_color)?;
+        let inset_color = parse_color(theme, "inset_color", &self.inset_color)?;
+        let text_color = parse_color(theme, "text_color", &self.text_color)?;
+        let optional_color = |field, value: &Option<String>, default| match value {
+            Some(value) => parse_color(theme, field, value),
+            None => Ok(default),
+        };
+        Ok(TileTheme {
+            border_color,
+            inset_color,
+            background_color: parse_color(theme, "background_color", &self.background_color)?,
+            text_color,
+            progress_bar_color: optional_color(
+                "progress_bar_color",
+                &self.progress_bar_color,
+                text_color,
+            )?,
+            progress_track_color: optional_color(
+                "progress_track_color",
+                &self.progress_track_color,
+                inset_color,
+            )?,
+            image_treatment: self
+                .image_treatment
+                .unwrap_or(ImageTreatment::default_for(state)),
+        })
+    }
+
+    fn validate(&self, theme: &'static str) -> Vec<Problem> {
+        [
+            ("border_color", Some(&self.border_color)),
+            ("inset_color", Some(&self.inset_color)),
+            ("background_color", Some(&self.background_color)),
+            ("text_color", Some(&self.text_color)),
+            ("progress_bar_color", self.progress_bar_color.as_ref()),
+            ("progress_track_color", self.progress_track_color.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| {
+            let error = parse_color(theme, field, value?).err()?;
+            let path = format!("tile_render_options.{}.{}", theme, field);
+            Some(Problem::new(path, error))
+        })
+        .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TileRenderOptionsBuilder {
+    pub padding: u32,
+    pub border_size: u32,
+    pub inset_size: u32,
+    pub text_size: u32,
+    /// Long names are shrunk down to this size, defaults to 12 or `text_size` if that's smaller
+    #[serde(default)]
+    pub min_text_size: Option<u32>,
+    /// How many lines a name can wrap onto
+    #[serde(default = "default_max_name_lines")]
+    pub max_name_lines: usize,
+    /// Legacy name for `themes.revealed`
+    #[serde(default)]
+    pub locked_theme: Option<TileThemeBuilder>,
+    /// Legacy name for `themes.completed`
+    #[serde(default)]
+    pub unlocked_theme: Option<TileThemeBuilder>,
+    #[serde(default)]
+    pub themes: TileThemesBuilder,
+    /// How tile images are fitted onto tiles, tiles can override any of these
+    #[serde(default)]
+    pub image_options: ImageOptionsBuilder,
+}
+
+/// Per-state theme overrides, states that aren't listed use the default theme
+#[derive(Deserialize, Debug, Default)]
+pub struct TileThemesBuilder {
+    pub hidden: Option<TileThemeBuilder>,
+    pub revealed: Option<TileThemeBuilder>,
+    pub in_progress: Option<TileThemeBuilder>,
+    pub completed: Option<TileThemeBuilder>,
+    pub failed: Option<TileThemeBuilder>,
+}
+
+impl TileRenderOptionsBuilder {
+    pub fn build(&self) -> Result<TileRenderOptions, Error> {
+        if self.max_name_lines == 0 {
+            return Err(Error::ZeroMaxNameLines);
+        }
+        let mut themes = TileThemes::default();
+        for (name, state, builder) in self.theme_builders() {
+            *themes.get_mut(state) = builder.build(name, state)?;
+        }
+        Ok(TileRenderOptions {
+            padding: self.padding,
+            border_size: self.border_size,
+            inset_size: self.inset_size,
+            text_size: self.text_size,
+            min_text_size: self.min_text_size(),
+            max_name_lines: self.max_name_lines,
+            themes,
+        })
+    }
+
+    pub fn min_text_size(&self) -> u32 {
+        self.min_text_size
+            .unwrap_or(TileRenderOptions::default().min_text_size)
+            .min(self.text_size)
+    }
+
+    fn validate(&self) -> Vec<Problem> {
+        let mut problems = self
+            .theme_builders()
+            .into_iter()
+            .flat_map(|(name, _, builder)| builder.validate(name))
+            .collect::<Vec<_>>();
+        problems.extend(
+            self.image_options
+                .validate("tile_render_options.image_options"),
+        );
+        if self.max_name_lines == 0 {
+            problems.push(Problem::new(
+                "tile_render_options.max_name_lines",
+                Error::ZeroMaxNameLines,
+            ));
+        }
+        problems
+    }
+
+    /// Every theme given in the YAML along with its name and the state it applies to. Legacy
+    /// themes come first so that `themes` can override them.
+    fn theme_builders(&self) -> Vec<(&'static str, TileState, &TileThemeBuilder)> {
+        let themes = &self.themes;
+        [
+            ("locked_theme", TileState::Revealed, &self.locked_theme),
+            ("unlocked_theme", TileState::Completed, &self.unlocked_theme),
+            ("themes.hidden", TileState::Hidden, &themes.hidden),
+            ("themes.revealed", TileState::Revealed, &themes.revealed),
+            (
+                "themes.in_progress",
+                TileState::InProgress,
+                &themes.in_progress,
+            ),
+            ("themes.completed", TileState::Completed, &themes.completed),
+            ("themes.failed", TileState::Failed, &themes.failed),
+        ]
+        .into_iter()
+        .filter_map(|(name, state, builder)| Some((name, state, builder.as_ref()?)))
+        .collect()
+    }
+}
+
+fn default_max_name_lines() -> usize {
+    TileRenderOptions::default().max_name_lines
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LineHighlightBuilder {
+    pub color: String,
+    #[serde(default = "default_line_highlight_width")]
+    pub width: u32,
+}
+
+fn default_margin() -> u32 {
+    20
+}
+
+fn default_line_highlight_width() -> u32 {
+    12
+}
+
+impl LineHighlightBuilder {
+    pub fn build(&self) -> Result<LineHighlight, Error> {
+        Ok(LineHighlight {
+            color: parse_color("line_highlight", "color", &self.color)?,
+            width: self.width,
+        })
+    }
+}
+
+/// Text drawn around the tiles, anything that isn't given gets the default for the caption
+#[derive(Deserialize, Debug)]
+pub struct CaptionBuilder {
+    pub text: String,
+    #[serde(default)]
+    pub size: Option<u32>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub region: Option<Region>,
+}
+
+impl CaptionBuilder {
+    /// `name` is the field the caption was given in, for error reporting.
+    pub fn build(
+        &self,
+        name: &'static str,
+        defaults: (u32, Rgba, Region),
+    ) -> Result<Caption, Error> {
+        let (size, color, region) = defaults;
+        Ok(Caption {
+            text: self.text.clone(),
+            size: caption_size(name, self.size.unwrap_or(size))?,
+            color: match &self.color {
+                Some(value) => parse_color(name, "color", value)?,
+                None => color,
+            },
+            region: self.region.unwrap_or(region),
+        })
+    }
+}
+
+/// A key to the tile states on the board, generated from the states of its tiles
+#[derive(Deserialize, Debug, Default)]
+pub struct LegendBuilder {
+    #[serde(default)]
+    pub size: Option<u32>,
+    /// Defaults to the text color of each state's theme
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub region: Option<Region>,
+    /// Replaces the default labels for these states
+    #[serde(default)]
+    pub labels: HashMap<TileState, String>,
+}
+
+impl LegendBuilder {
+    pub fn build(&self) -> Result<Legend, Error> {
+        Ok(Legend {
+            size: caption_size("legend", self.size.unwrap_or(16))?,
+            color: self
+                .color
+                .as_ref()
+                .map(|value| parse_color("legend", "color", value))
+                .transpose()?,
+            region: self.region.unwrap_or(Region::Bottom),
+            labels: self.labels.clone(),
+        })
+    }
+}
+
+/// Checks that a caption's text size is one that can be drawn.
+fn caption_size(caption: &'static str, size: u32) -> Result<u32, Error> {
+    if size == 0 || size > MAX_TEXT_SIZE {
+        return Err(Error::InvalidCaptionSize {
+            caption,
+            size,
+            max: MAX_TEXT_SIZE,
+        });
+    }
+    Ok(size)
+}
+
+/// Builds every caption, with the defaults for each.
+fn build_captions(
+    title: Option<&CaptionBuilder>,
+    subtitle: Option<&CaptionBuilder>,
+    legend: Option<&LegendBuilder>,
+    footer: Option<&CaptionBuilder>,
+) -> Result<Captions, Error> {
+    Ok(Captions {
+        title: title
+            .map(|c| c.build("title", (40, ORANGE, Region::Top)))
+            .transpose()?,
+        subtitle: subtitle
+            .map(|c| c.build("subtitle", (24, YELLOW, Region::Top)))
+            .transpose()?,
+        legend: legend.map(LegendBuilder::build).transpose()?,
+        footer: footer
+            .map(|c| c.build("footer", (16, GRAY, Region::Bottom)))
+            .transpose()?,
+    })
+}
+
+/// A generated background, drawn behind `image` if the board has one
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundBuilder {
+    Color(String),
+    LinearGradient {
+        from: String,
+        to: String,
+        /// In degrees clockwise from left to right, so 90 fades from top to bottom
+        #[serde(default = "default_gradient_angle")]
+        angle: f32,
+    },
+    RadialGradient {
+        inner: String,
+        outer: String,
+    },
+    /// A path or URL of an image that's repeated across the board
+    Texture(String),
+}
+
+fn default_gradient_angle() -> f32 {
+    90.0
+}
+
+impl BackgroundBuilder {
+    pub fn build(&self, image_loader: &ImageLoader) -> Result<Background, Error> {
+        Ok(match self {
+            BackgroundBuilder::Color(value) => {
+                Background::Color(parse_color("background", "color", value)?)
+            }
+            BackgroundBuilder::LinearGradient { from, to, angle } => {
+                let theme = "background.linear_gradient";
+                Background::LinearGradient {
+                    from: parse_color(theme, "from", from)?,
+                    to: parse_color(theme, "to", to)?,
+                    angle: *angle,
+                }
+            }
+            BackgroundBuilder::RadialGradient { inner, outer } => {
+                let theme = "background.radial_gradient";
+                Background::RadialGradient {
+                    inner: parse_color(theme, "inner", inner)?,
+                    outer: parse_color(theme, "outer", outer)?,
+                }
+            }
+            BackgroundBuilder::Texture(location) => {
+                let texture = image_loader
+                    .load(location)
+                    .map_err(|source| background_failed(location, source))?;
+                Background::Texture(texture)
+            }
+        })
+    }
+
+    /// The texture's location, the only image a background needs.
+    pub fn location(&self) -> Option<&str> {
+        match self {
+            BackgroundBuilder::Texture(location) => Some(location),
+            _ => None,
+        }
+    }
+
+    fn validate(&self, image_loader: &ImageLoader) -> Vec<Problem> {
+        let colors = match self {
+            BackgroundBuilder::Color(value) => vec![("background", "color", value)],
+            BackgroundBuilder::LinearGradient { from, to, .. } => vec![
+                ("background.linear_gradient", "from", from),
+                ("background.linear_gradient", "to", to),
+            ],
+            BackgroundBuilder::RadialGradient { inner, outer } => vec![
+                ("background.radial_gradient", "inner", inner),
+                ("background.radial_gradient", "outer", outer),
+            ],
+            BackgroundBuilder::Texture(location) => {
+                return match image_loader.check(location) {
+                    Ok(()) => Vec::new(),
+                    Err(source) => vec![Problem::new(
+                        "background.texture",
+                        unreachable_image(location, source),
+                    )],
+                };
+            }
+        };
+        colors
+            .into_iter()
+            .filter_map(|(theme, field, value)| {
+                let error = parse_color(theme, field, value).err()?;
+                Some(Problem::new(format!("{}.{}", theme, field), error))
+            })
+            .collect()
+    }
+}
+
+/// A board as it's written in YAML, see the README for every field
 #[derive(Deserialize, Debug)]
 pub struct BoardBuilder {
     pub rows: usize,
     pub cols: usize,
-    pub content_rect: ContentRect,
-    pub tile_size: u32,
-    pub tile_render_options: TileRenderOptions,
-    pub image: String,
+    /// Defaults to the whole board, less `margin` on every side
+    #[serde(default)]
+    pub content_rect: Option<ContentRect>,
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+    pub tile_size: TileSize,
+    /// The least space between two tiles in a row, and between rows
+    #[serde(default)]
+    pub gap_x: u32,
+    #[serde(default)]
+    pub gap_y: u32,
+    #[serde(default)]
+    pub spacing: Spacing,
+    pub tile_render_options: TileRenderOptionsBuilder,
+    /// The background image, which sets the size of the board unless `width` and `height` do
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub background: Option<BackgroundBuilder>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
     pub tiles: Vec<TileBuilder>,
+    #[serde(default)]
+    pub scoring: ScoringOptions,
+    /// Strikes through completed lines when set
+    #[serde(default)]
+    pub line_highlight: Option<LineHighlightBuilder>,
+    #[serde(default)]
+    pub title: Option<CaptionBuilder>,
+    #[serde(default)]
+    pub subtitle: Option<CaptionBuilder>,
+    #[serde(default)]
+    pub legend: Option<LegendBuilder>,
+    #[serde(default)]
+    pub footer: Option<CaptionBuilder>,
 }
 
 impl BoardBuilder {
-    pub fn build(self, image_loader: &ImageLoader) -> Result<Board, BoardBuilderError> {
+    /// Runs every check that doesn't require downloading images, collecting all of the
+    /// problems found rather than stopping at the first one. The background image is only
+    /// checked against `content_rect` if it is available locally.
+    pub fn validate(
+        &self,
+        image_loader: &ImageLoader,
+        text_renderer: &TextRenderer,
+    ) -> Vec<Diagnostic> {
+        let mut problems = Vec::new();
+        let mut diagnostics = Vec::new();
+        problems.extend(validate_tile_count(self.rows, self.cols, &self.tiles));
+        problems.extend(validate_tile_numbers(&self.tiles));
+        problems.extend(validate_tile_progress(&self.tiles));
+        problems.extend(validate_tile_images(&self.tiles));
+        problems.extend(validate_tile_image_options(&self.tiles));
+        problems.extend(self.tile_render_options.validate());
+        if let Some(Err(error)) = self
+            .line_highlight
+            .as_ref()
+            .map(LineHighlightBuilder::build)
+        {
+            problems.push(Problem::new("line_highlight.color", error));
+        }
+        // colors and sizes are checked here so that every problem is reported, not just the first
+        let captions = [
+            ("title", self.title.as_ref().map(|c| (&c.color, c.size))),
+            (
+                "subtitle",
+                self.subtitle.as_ref().map(|c| (&c.color, c.size)),
+            ),
+            ("legend", self.legend.as_ref().map(|l| (&l.color, l.size))),
+            ("footer", self.footer.as_ref().map(|c| (&c.color, c.size))),
+        ];
+        for (name, (color, size)) in captions
+            .into_iter()
+            .filter_map(|(name, caption)| Some((name, caption?)))
+        {
+            let color = color.as_ref();
+            if let Some(Err(error)) = color.map(|value| parse_color(name, "color", value)) {
+                problems.push(Problem::new(format!("{}.color", name), error));
+            }
+            if let Some(Err(error)) = size.map(|size| caption_size(name, size)) {
+                problems.push(Problem::new(format!("{}.size", name), error));
+            }
+        }
+        for (i, tile) in self.tiles.iter().enumerate() {
+            if tile.state.is_some() && tile.unlocked.is_some() {
+                let diagnostic = Diagnostic::warning(format!(
+                    "tile {} sets both state and unlocked, unlocked is ignored",
+                    tile.number
+                ))
+                .at(format!("tiles[{}].unlocked", i));
+                diagnostics.push(diagnostic);
+            }
+            for (field, location) in tile.image_locations() {
+                if let Err(source) = image_loader.check(&location) {
+                    let error = unreachable_image(&location, source);
+                    problems.push(Problem::new(format!("tiles[{}].{}", i, field), error));
+                }
+            }
+        }
+        if let Some(background) = &self.background {
+            problems.extend(background.validate(image_loader));
+        }
+        let image_dimensions = match &self.image {
+            Some(image) => match image_loader.cached_dimensions(image) {
+                Ok(Some(dimensions)) => Some(dimensions),
+                Ok(None) if image_loader.is_offline() => {
+                    let location = images::display_location(image).into_owned();
+                    let error = Error::NotCached {
+                        locations: vec![location],
+                    };
+                    problems.push(Problem::new("image", error));
+                    None
+                }
+                Ok(None) => {
+                    if self.canvas_size(None).is_none() {
+                        diagnostics.push(
+                            Diagnostic::note("background image isn't cached, so the board's size and content_rect can't be checked")
+                                .at("image"),
+                        );
+                    }
+                    None
+                }
+                Err(source) => {
+                    let error = unreachable_image(image, source);
+                    problems.push(Problem::new("image", error));
+                    None
+                }
+            },
+            None => {
+                for (axis, value) in [(Axis::X, self.width), (Axis::Y, self.height)] {
+                    if value.is_none() {
+                        let error = Error::MissingCanvasSize { axis };
+                        problems.push(Problem::new(axis.dimension(), error));
+                    }
+                }
+                None
+            }
+        };
+        let canvas = self.canvas_size(image_dimensions);
+        if let (Some(canvas), Some(content_rect)) = (canvas, &self.content_rect) {
+            problems.extend(validate_content_rect(canvas, content_rect));
+        }
+        let mut content_rect = self.resolved_content_rect(canvas);
+        if let (Some(canvas), None) = (canvas, &self.content_rect) {
+            let margin_problems = validate_margin(canvas, self.margin, (self.width, self.height));
+            // there's nowhere for the tiles to go, so there's nothing more to check
+            if !margin_problems.is_empty() {
+                content_rect = None;
+            }
+            problems.extend(margin_problems);
+        }
+        if let Some(content_rect) = &content_rect {
+            problems.extend(validate_grid(
+                content_rect,
+                self.resolved_tile_size(content_rect),
+                self.rows,
+                self.cols,
+                (self.gap_x, self.gap_y),
+                self.spacing,
+            ));
+        }
+        diagnostics.extend(problems.into_iter().map(Diagnostic::from));
+        // an auto tile size can't be worked out without knowing where the tiles go
+        let tile_size = match (self.tile_size, &content_rect) {
+            (TileSize::Fixed(size), _) => Some(size),
+            // `validate_grid` has already reported an auto size that came out as 0
+            (TileSize::Auto, Some(content_rect)) => {
+                Some(self.resolved_tile_size(content_rect)).filter(|&size| size > 0)
+            }
+            (TileSize::Auto, None) => None,
+        };
+        if let Some(tile_size) = tile_size {
+            let options = &self.tile_render_options;
+            let insets = (options.border_size, options.inset_size, options.padding);
+            let problems = validate_tile_size(tile_size, insets);
+            diagnostics.extend(problems.into_iter().map(Diagnostic::from));
+            diagnostics.extend(self.validate_tile_names(tile_size, text_renderer));
+        }
+        if let (Some(canvas), Some(content_rect)) = (canvas, &content_rect) {
+            diagnostics.extend(self.validate_captions(canvas, content_rect, text_renderer));
+        }
+        diagnostics
+    }
+
+    /// Every image the board needs, background first.
+    pub fn image_locations(&self) -> Vec<Cow<'_, str>> {
+        image_locations(self.image.as_deref(), self.background.as_ref(), &self.tiles)
+    }
+
+    /// The size of the board, from `width` and `height` where they're given and the background
+    /// image otherwise, if its `image_dimensions` are known.
+    pub fn canvas_size(&self, image_dimensions: Option<(u32, u32)>) -> Option<(u32, u32)> {
+        canvas_size(self.width, self.height, image_dimensions)
+    }
+
+    /// `content_rect`, or the default for a board of size `canvas`.
+    pub fn resolved_content_rect(&self, canvas: Option<(u32, u32)>) -> Option<ContentRect> {
+        self.content_rect
+            .or_else(|| canvas.map(|canvas| ContentRect::with_margin(canvas, self.margin)))
+    }
+
+    /// The tile size in pixels, working out the largest tile that fits with at least `gap_x`
+    /// and `gap_y` between tiles for `tile_size: auto`.
+    pub fn resolved_tile_size(&self, content_rect: &ContentRect) -> u32 {
+        resolve_tile_size(
+            self.tile_size,
+            content_rect,
+            (self.rows, self.cols),
+            (self.gap_x, self.gap_y),
+            self.spacing,
+        )
+    }
+
+    /// Warns about tile names that don't fit in the space available to them, even after
+    /// wrapping and shrinking.
+    fn validate_tile_names(&self, tile_size: u32, text_renderer: &TextRenderer) -> Vec<Diagnostic> {
+        let options = &self.tile_render_options;
+        // tiles without any room inside them are reported by `validate_tile_size`, and a
+        // zero line limit by `TileRenderOptionsBuilder::validate`
+        if options.max_name_lines == 0 {
+            return Vec::new();
+        }
+        let Some((x1, _, x2, _)) = compute_content_bounds(
+            tile_size,
+            options.border_size,
+            options.inset_size,
+            options.padding,
+        ) else {
+            return Vec::new();
+        };
+        let available = x2 - x1;
+        let min_text_size = options.min_text_size();
+        let text_options = TextRenderOptions {
+            size: options.text_size as f32,
+            ..Default::default()
+        };
+        self.tiles
+            .iter()
+            .enumerate()
+            // hidden tiles show "???" instead of their name
+            .filter(|(_, tile)| tile.state() != TileState::Hidden)
+            .filter_map(|(i, tile)| {
+                let fitted = text_renderer.fit(
+                    &tile.name,
+                    available,
+                    options.max_name_lines,
+                    min_text_size as f32,
+                    &text_options,
+                );
+                if fitted.fits {
+                    return None;
+                }
+                let message = format!(
+                    "name of tile {} doesn't fit in {} line{} of {}px, even at text size {}",
+                    tile.number,
+                    options.max_name_lines,
+                    if options.max_name_lines == 1 { "" } else { "s" },
+                    available,
+                    min_text_size
+                );
+                let diagnostic = Diagnostic::warning(message)
+                    .at(format!("tiles[{}].name", i))
+                    .with_note(
+                        "shorten the name, or lower tile_render_options.min_text_size or raise max_name_lines",
+                    );
+                Some(diagnostic)
+            })
+            .collect()
+    }
+
+    /// Warns about regions that the captions in them don't fit in.
+    fn validate_captions(
+        &self,
+        canvas: (u32, u32),
+        content_rect: &ContentRect,
+        text_renderer: &TextRenderer,
+    ) -> Vec<Diagnostic> {
+        // anything that doesn't build has already been reported
+        let Ok(captions) = build_captions(
+            self.title.as_ref(),
+            self.subtitle.as_ref(),
+            self.legend.as_ref(),
+            self.footer.as_ref(),
+        ) else {
+            return Vec::new();
+        };
+        let Ok(options) = self.tile_render_options.build() else {
+            return Vec::new();
+        };
+        if captions.is_empty() {
+            return Vec::new();
+        }
+        let states = self
+            .tiles
+            .iter()
+            .map(TileBuilder::state)
+            .collect::<Vec<_>>();
+        let context = CaptionContext {
+            dimensions: canvas,
+            content_rect: content_rect.bounds(),
+            themes: &options.themes,
+            states: &states,
+            team: None,
+        };
+        let regions = [
+            ("title", captions.title.as_ref().map(|c| c.region)),
+            ("subtitle", captions.subtitle.as_ref().map(|c| c.region)),
+            ("legend", captions.legend.as_ref().map(|l| l.region)),
+            ("footer", captions.footer.as_ref().map(|c| c.region)),
+        ];
+        captions
+            .overflows(text_renderer, &context)
+            .into_iter()
+            .map(|(region, needed, available)| {
+                let message = format!(
+                    "the {} region is {}px tall, but the text in it needs {}px",
+                    region, available, needed
+                );
+                let diagnostic = Diagnostic::warning(message).with_note(
+                    "make room with content_rect or margin, or lower the size of the text",
+                );
+                // every overflowing region comes from one of the captions, but point at
+                // nothing rather than the wrong field if that ever changes
+                match regions.iter().find(|(_, r)| *r == Some(region)) {
+                    Some((name, _)) => diagnostic.at(*name),
+                    None => diagnostic,
+                }
+            })
+            .collect()
+    }
+
+    /// Checks the board, stopping at the first problem (see `validate` to get all of them),
+    /// and loads every image it needs through `image_loader`.
+    pub fn build(self, image_loader: &ImageLoader) -> Result<Board, Error> {
         let BoardBuilder {
             rows,
             cols,
             content_rect,
+            margin,
             tile_size,
+            gap_x,
+            gap_y,
+            spacing,
             tile_render_options,
             image,
+            background,
+            width,
+            height,
             mut tiles,
+            scoring,
+            line_highlight,
+            title,
+            subtitle,
+            legend,
+            footer,
         } = self;
 
         // tile validation stuff
-        tiles.sort_by(|a, b| a.number.cmp(&b.number));
-        validate_tile_count(rows as usize, cols as usize, &tiles)?;
-        validate_tile_numbers(&tiles)?;
-
-        let background_image = image_loader
-            .load(&image)
-            .map_err(BoardBuilderError::Wrapped)?;
-        validate_content_rect(
-            background_image.dimensions(),
+        tiles.sort_by_key(|t| t.number);
+        first_error(validate_tile_count(rows, cols, &tiles))?;
+        first_error(validate_tile_numbers(&tiles))?;
+        first_error(validate_tile_progress(&tiles))?;
+        first_error(validate_tile_images(&tiles))?;
+        first_error(validate_tile_image_options(&tiles))?;
+        first_error(tile_render_options.validate())?;
+        let image_options = tile_render_options.image_options.clone();
+        let tile_render_options = tile_render_options.build()?;
+        let line_highlight = line_highlight
+            .as_ref()
+            .map(LineHighlightBuilder::build)
+            .transpose()?;
+        let captions = build_captions(
+            title.as_ref(),
+            subtitle.as_ref(),
+            legend.as_ref(),
+            footer.as_ref(),
+        )?;
+
+        // when offline, report everything that's missing up front rather than one at a time
+        image_loader.require_cached(&image_locations(
+            image.as_deref(),
+            background.as_ref(),
+            &tiles,
+        ))?;
+        let background = background
+            .as_ref()
+            .map(|background| background.build(image_loader))
+            .transpose()?;
+        let background_image = image
+            .as_ref()
+            .map(|image| {
+                image_loader
+                    .load(image)
+                    .map_err(|source| background_failed(image, source))
+            })
+            .transpose()?;
+
+        // work out where everything goes now that the background image's size is known
+        let image_dimensions = background_image.as_ref().map(Image::dimensions);
+        let explicit_size = (width, height);
+        let (width, height) = match canvas_size(width, height, image_dimensions) {
+            Some(canvas) => canvas,
+            None => {
+                let axis = if width.is_none() { Axis::X } else { Axis::Y };
+                return Err(Error::MissingCanvasSize { axis });
+            }
+        };
+        match &content_rect {
+            Some(content_rect) => {
+                first_error(validate_content_rect((width, height), content_rect))?;
+            }
+            None => first_error(validate_margin((width, height), margin, explicit_size))?,
+        }
+        let content_rect =
+            content_rect.unwrap_or_else(|| ContentRect::with_margin((width, height), margin));
+        let tile_size = resolve_tile_size(
+            tile_size,
+            &content_rect,
+            (rows, cols),
+            (gap_x, gap_y),
+            spacing,
+        );
+        first_error(validate_grid(
             &content_rect,
             tile_size,
             rows,
             cols,
-        )?;
+            (gap_x, gap_y),
+            spacing,
+        ))?;
+        let insets = (
+            tile_render_options.border_size,
+            tile_render_options.inset_size,
+            tile_render_options.padding,
+        );
+        first_error(validate_tile_size(tile_size, insets))?;
 
         // build tiles
-        let tiles = build_tiles(&tiles, image_loader).map_err(BoardBuilderError::Wrapped)?;
+        let tiles = build_tiles(&tiles, &image_options, image_loader)?;
 
-        // shadowing to make the syntax below a bit neater
-        let image = background_image;
-        let content_rect = (
-            content_rect.x1,
-            content_rect.y1,
-            content_rect.x2,
-            content_rect.y2,
-        );
+        let image = background::compose(background.as_ref(), background_image, width, height);
+        let content_rect = content_rect.bounds();
 
         Ok(Board {
             rows,
             cols,
             content_rect,
             tile_size,
+            spacing,
             tile_render_options,
             tiles,
             image,
+            scoring,
+            line_highlight,
+            captions,
+            team: None,
         })
     }
 }
 
-fn build_tiles(tiles: &[TileBuilder], image_loader: &ImageLoader) -> Result<Vec<Tile>, AppError> {
+/// Names the location a check failed for, unless the error already does.
+fn unreachable_image(location: &str, source: Error) -> Error {
+    match source {
+        Error::NotCached { .. } | Error::InvalidLocation { .. } => source,
+        source => Error::Load {
+            location: images::display_location(location).into_owned(),
+            source: Box::new(source),
+        },
+    }
+}
+
+/// The error for a background image or texture that couldn't be loaded.
+fn background_failed(location: &str, source: Error) -> Error {
+    Error::ImageFailed {
+        tiles: Vec::new(),
+        location: images::display_location(location).into_owned(),
+        source: Box::new(source),
+    }
+}
+
+/// Every image a board needs, background first.
+fn image_locations<'a>(
+    image: Option<&'a str>,
+    background: Option<&'a BackgroundBuilder>,
+    tiles: &'a [TileBuilder],
+) -> Vec<Cow<'a, str>> {
+    let mut locations = image
+        .into_iter()
+        .chain(background.and_then(BackgroundBuilder::location))
+        .map(Cow::Borrowed)
+        .collect::<Vec<_>>();
+    locations.extend(
+        tiles
+            .iter()
+            .flat_map(|tile| tile.image_locations().into_iter().map(|(_, l)| l)),
+    );
+    locations
+}
+
+fn build_tiles(
+    tiles: &[TileBuilder],
+    image_options: &ImageOptionsBuilder,
+    image_loader: &ImageLoader,
+) -> Result<Vec<Tile>, Error> {
+    // images are fetched concurrently, everything else is cheap enough to do in order
+    // every tile has a location once `validate_tile_images` has passed
+    let tile_locations = tiles
+        .iter()
+        .map(|t| {
+            let locations = t.image_locations().into_iter().map(|(_, l)| l);
+            locations.collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let locations = tile_locations.concat();
+    let mut images = image_loader
+        .load_all(&locations)
+        .map_err(|error| match error {
+            Error::Load { location, source } => {
+                // name every tile that uses the image
+                let numbers = tiles
+                    .iter()
+                    .zip(tile_locations.iter())
+                    .filter(|(_, locations)| {
+                        locations
+                            .iter()
+                            .any(|l| images::display_location(l) == location)
+                    })
+                    .map(|(tile, _)| tile.number)
+                    .collect();
+                Error::ImageFailed {
+                    tiles: numbers,
+                    location,
+                    source,
+                }
+            }
+            error => error,
+        })?
+        .into_iter();
     let mut result = Vec::with_capacity(tiles.len());
-    for builder in tiles.iter() {
+    for (builder, locations) in tiles.iter().zip(tile_locations.iter()) {
+        let tile_images = images.by_ref().take(locations.len()).collect();
         let number = builder.number;
         let name = builder.name.clone();
-        let image = image_loader.load(&builder.image)?;
-        let unlocked = builder.unlocked;
+        let state = builder.state();
+        let progress = builder.progress;
+        let image_options = match &builder.image_options {
+            Some(overrides) => image_options.merge(overrides).build(),
+            None => image_options.build(),
+        };
         let tile = Tile {
             number,
             name,
-            image,
-            unlocked,
+            images: tile_images,
+            image_options,
+            state,
+            progress,
         };
         result.push(tile);
     }
     Ok(result)
 }
 
-fn validate_content_rect(
+fn parse_color(theme: &'static str, field: &'static str, value: &str) -> Result<Rgba, Error> {
+    Rgba::from_hex(value).map_err(|e| Error::InvalidColor {
+        theme,
+        field,
+        value: value.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// The size of a board, `width` and `height` win over the background image's dimensions.
+fn canvas_size(
+    width: Option<u32>,
+    height: Option<u32>,
+    image_dimensions: Option<(u32, u32)>,
+) -> Option<(u32, u32)> {
+    let (image_width, image_height) = image_dimensions.unzip();
+    Some((width.or(image_width)?, height.or(image_height)?))
+}
+
+/// The tile size in pixels, working out the largest tile that fits with at least `gaps`
+/// between tiles for `tile_size: auto`.
+fn resolve_tile_size(
+    tile_size: TileSize,
+    content_rect: &ContentRect,
+    (rows, cols): (usize, usize),
+    (gap_x, gap_y): (u32, u32),
+    spacing: Spacing,
+) -> u32 {
+    if let TileSize::Fixed(size) = tile_size {
+        return size;
+    }
+    let ContentRect { x1, y1, x2, y2 } = *content_rect;
+    // 0 when not even 1px tiles fit, which `validate_grid` reports
+    let fit = |available: u32, count: usize, gap: u32| {
+        let count = u32::try_from(count.max(1)).unwrap_or(u32::MAX);
+        let gaps = gap.saturating_mul(spacing.gaps(count));
+        available.saturating_sub(gaps) / count
+    };
+    u32::min(
+        fit(x2.saturating_sub(x1), cols, gap_x),
+        fit(y2.saturating_sub(y1), rows, gap_y),
+    )
+}
+
+/// Checks that content_rect fits on a board of `dimensions`.
+fn validate_content_rect(dimensions: (u32, u32), content_rect: &ContentRect) -> Vec<Problem> {
+    let (width, height) = dimensions;
+    let axes = [
+        (Axis::X, content_rect.x1, content_rect.x2, width),
+        (Axis::Y, content_rect.y1, content_rect.y2, height),
+    ];
+    let mut problems = Vec::new();
+    for (axis, start, end, limit) in axes {
+        if start < end && end > limit {
+            let error = Error::ContentRectOutOfBounds { axis, end, limit };
+            problems.push(Problem::new(format!("content_rect.{}2", axis), error));
+        }
+    }
+    problems
+}
+
+/// Checks that `margin` leaves some room on a board of `dimensions` when there's no
+/// content_rect. Problems go on `width` or `height` when those are given and the margin is the
+/// default, since they're what was changed.
+fn validate_margin(
     dimensions: (u32, u32),
+    margin: u32,
+    explicit: (Option<u32>, Option<u32>),
+) -> Vec<Problem> {
+    let (width, height) = dimensions;
+    let axes = [(Axis::X, width, explicit.0), (Axis::Y, height, explicit.1)];
+    let mut problems = Vec::new();
+    for (axis, size, explicit) in axes {
+        if margin.saturating_mul(2) < size {
+            continue;
+        }
+        let path = if margin == default_margin() && explicit.is_some() {
+            axis.dimension()
+        } else {
+            "margin"
+        };
+        let error = Error::MarginTooLarge { axis, margin, size };
+        problems.push(Problem::new(path, error));
+    }
+    problems
+}
+
+/// Checks that `rows` by `cols` tiles, with their gaps, fit in content_rect.
+fn validate_grid(
     content_rect: &ContentRect,
     tile_size: u32,
     rows: usize,
     cols: usize,
-) -> Result<(), BoardBuilderError> {
-    // TODO: better information on what's actually wrong
-    let (width, height) = dimensions;
-    if content_rect.x1 >= content_rect.x2 || content_rect.y1 >= content_rect.y2 {
-        return Err(BoardBuilderError::InvalidDimensions {
-            width,
-            height,
-            content_rect: content_rect.clone(),
-        });
-    }
-    let rect_width = content_rect.x2 - content_rect.x1;
-    let rect_height = content_rect.y2 - content_rect.y1;
-    if rect_width > width || rect_height > height {
-        return Err(BoardBuilderError::InvalidDimensions {
-            width,
-            height,
-            content_rect: content_rect.clone(),
-        });
+    gaps: (u32, u32),
+    spacing: Spacing,
+) -> Vec<Problem> {
+    let axes = [
+        (Axis::X, content_rect.x1, content_rect.x2, cols, gaps.0),
+        (Axis::Y, content_rect.y1, content_rect.y2, rows, gaps.1),
+    ];
+    let mut problems = Vec::new();
+    for (axis, start, end, count, gap) in axes {
+        if start >= end {
+            let error = Error::InvertedContentRect { axis, start, end };
+            problems.push(Problem::new(format!("content_rect.{}2", axis), error));
+            continue;
+        }
+        let available = end - start;
+        // an auto tile size of 0 means that not even 1px tiles fit
+        let tile_size = tile_size.max(1);
+        let count_u32 = u32::try_from(count).unwrap_or(u32::MAX);
+        let needed = tile_size
+            .saturating_mul(count_u32)
+            .saturating_add(gap.saturating_mul(spacing.gaps(count_u32)));
+        if needed > available {
+            let error = Error::TilesDoNotFit {
+                axis,
+                count,
+                tile_size,
+                gap,
+                needed,
+                available,
+            };
+            problems.push(Problem::new("tile_size", error));
+        }
     }
-    let tile_width = tile_size * cols as u32;
-    let tile_height = tile_size * rows as u32;
-    if tile_width > rect_width || tile_height > rect_height {
-        return Err(BoardBuilderError::InvalidDimensions {
-            width,
-            height,
-            content_rect: content_rect.clone(),
-        });
+    problems
+}
+
+/// Checks that tiles have room for their content inside the border, inset and padding.
+fn validate_tile_size(tile_size: u32, (border, inset, padding): (u32, u32, u32)) -> Vec<Problem> {
+    if compute_content_bounds(tile_size, border, inset, padding).is_some() {
+        return Vec::new();
     }
-    Ok(())
+    let error = Error::TileTooSmall {
+        tile_size,
+        border,
+        inset,
+        padding,
+    };
+    vec![Problem::new("tile_size", error)]
 }
 
-fn validate_tile_count(
-    rows: usize,
-    cols: usize,
-    tiles: &[TileBuilder],
-) -> Result<(), BoardBuilderError> {
+fn validate_tile_count(rows: usize, cols: usize, tiles: &[TileBuilder]) -> Vec<Problem> {
     let expected = rows * cols;
     let actual = tiles.len();
     if expected != actual {
-        return Err(BoardBuilderError::WrongNumberOfTiles { expected, actual });
+        let error = Error::WrongNumberOfTiles { expected, actual };
+        return vec![Problem::new("tiles", error)];
     }
-    Ok(())
+    Vec::new()
 }
 
-fn validate_tile_numbers(tiles: &[TileBuilder]) -> Result<(), BoardBuilderError> {
-    let mut missing = (1..=tiles.len()).map(|x| x as u8).collect::<HashSet<_>>();
-    let mut unexpected = HashSet::new();
-    for tile in tiles {
-        if !missing.remove(&tile.number) {
-            unexpected.insert(tile.number);
+fn validate_tile_numbers(tiles: &[TileBuilder]) -> Vec<Problem> {
+    let count = tiles.len();
+    let mut missing = (1..=count).map(|x| x as u8).collect::<BTreeSet<_>>();
+    let mut seen = HashMap::new();
+    let mut problems = Vec::new();
+    for (i, tile) in tiles.iter().enumerate() {
+        let number = tile.number;
+        let path = format!("tiles[{}].number", i);
+        if let Some(&first) = seen.get(&number) {
+            let error = Error::DuplicateTile { number, first };
+            problems.push(Problem::new(path, error));
+        } else if !missing.remove(&number) {
+            let error = Error::UnexpectedTile { number, count };
+            problems.push(Problem::new(path, error));
         }
-    }
-    if !unexpected.is_empty() {
-        return Err(BoardBuilderError::UnexpectedTiles(unexpected));
+        seen.entry(number).or_insert(i);
     }
     if !missing.is_empty() {
-        return Err(BoardBuilderError::MissingTiles(missing));
+        problems.push(Problem::new("tiles", Error::MissingTiles(missing)));
+    }
+    problems
+}
+
+fn validate_tile_images(tiles: &[TileBuilder]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (i, tile) in tiles.iter().enumerate() {
+        let number = tile.number;
+        if tile.image.is_some() && tile.item.is_some() {
+            let error = Error::ImageAndItem { number };
+            problems.push(Problem::new(format!("tiles[{}].item", i), error));
+        } else if tile.image_locations().is_empty() {
+            let error = Error::MissingImage { number };
+            problems.push(Problem::new(format!("tiles[{}]", i), error));
+        }
+    }
+    problems
+}
+
+fn validate_tile_image_options(tiles: &[TileBuilder]) -> Vec<Problem> {
+    tiles
+        .iter()
+        .enumerate()
+        .flat_map(|(i, tile)| match &tile.image_options {
+            Some(options) => options.validate(&format!("tiles[{}].image_options", i)),
+            None => Vec::new(),
+        })
+        .collect()
+}
+
+fn validate_tile_progress(tiles: &[TileBuilder]) -> Vec<Problem> {
+    tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| tile.progress.is_some_and(|p| p.target == 0))
+        .map(|(i, tile)| {
+            let error = Error::ZeroProgressTarget {
+                number: tile.number,
+            };
+            Problem::new(format!("tiles[{}].progress.target", i), error)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ril::{Image, ImageFormat};
+
+    use super::*;
+    use crate::{images::ImageLoaderOptions, test_util::TempDir};
+
+    /// A 2x1 PNG, so that tiles have an image without touching the network or the disk.
+    const PIXEL: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAADklEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII=";
+
+    #[test]
+    fn it_validates_everything_at_once() {
+        let dir = TempDir::new("validate");
+        let background = dir.join("background.png");
+        Image::new(100, 100, Rgba::black())
+            .save(ImageFormat::Png, &background)
+            .unwrap();
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.join("cache"),
+            ..Default::default()
+        })
+        .unwrap();
+        let yaml = format!(
+            r##"
+rows: 2
+cols: 2
+content_rect: {{x1: 0, y1: 0, x2: 200, y2: 100}}
+tile_size: 40
+image: "{}"
+tile_render_options:
+  padding: 4
+  border_size: 2
+  inset_size: 2
+  text_size: 16
+  themes:
+    completed:
+      border_color: "#000000FF"
+      inset_color: "#000000FF"
+      background_color: "nope"
+      text_color: "#FFFFFFFF"
+tiles:
+  - {{number: 1, name: One, image: "{PIXEL}"}}
+  - {{number: 1, name: Again, image: "{PIXEL}"}}
+  - {{number: 3, name: Three, image: "{PIXEL}"}}
+"##,
+            background.display()
+        );
+        let config: BoardBuilder = serde_yaml::from_str(&yaml).unwrap();
+        let diagnostics = config.validate(&image_loader, &TextRenderer::default());
+        let codes = diagnostics
+            .iter()
+            .filter_map(|diagnostic| diagnostic.code)
+            .collect::<BTreeSet<_>>();
+        // wrong tile count, a duplicate, a missing tile, a bad color and an oversized rect
+        for code in ["E0502", "E0505", "E0503", "E0512", "E0507"] {
+            assert!(codes.contains(code), "expected {} in {:?}", code, codes);
+        }
+        let color = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.code == Some("E0512"))
+            .unwrap();
+        assert_eq!(
+            color.path.as_deref(),
+            Some("tile_render_options.themes.completed.background_color")
+        );
+        // build stops at the first of them
+        assert!(matches!(
+            config.build(&image_loader),
+            Err(Error::WrongNumberOfTiles {
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn it_places_tiles_where_boards_without_spacing_did() {
+        let dir = TempDir::new("placement");
+        let background = dir.join("background.png");
+        Image::new(400, 300, Rgba::black())
+            .save(ImageFormat::Png, &background)
+            .unwrap();
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.join("cache"),
+            ..Default::default()
+        })
+        .unwrap();
+        let tiles = (1..=6)
+            .map(|n| format!("  - {{number: {n}, name: Tile, image: \"{PIXEL}\"}}\n"))
+            .collect::<String>();
+        let yaml = format!(
+            r##"
+rows: 2
+cols: 3
+content_rect: {{x1: 10, y1: 20, x2: 330, y2: 250}}
+tile_size: 100
+image: "{}"
+tile_render_options:
+  padding: 4
+  border_size: 2
+  inset_size: 2
+  text_size: 16
+tiles:
+{tiles}"##,
+            background.display()
+        );
+        let board = serde_yaml::from_str::<BoardBuilder>(&yaml)
+            .unwrap()
+            .build(&image_loader)
+            .unwrap();
+        // the leftover space divided by the tile count goes after every tile
+        let (x_pad, y_pad) = ((320 - 300) / 3, (230 - 200) / 2);
+        let expected = (0..6)
+            .map(|i| (10 + (i % 3) * (100 + x_pad), 20 + (i / 3) * (100 + y_pad)))
+            .collect::<Vec<_>>();
+        assert_eq!(board.tile_positions(), expected);
+    }
+
+    #[test]
+    fn it_picks_themes_by_state() {
+        let theme = |color| {
+            format!(
+                "{{border_color: \"{color}\", inset_color: \"{color}\", background_color: \"{color}\", text_color: \"{color}\"}}"
+            )
+        };
+        let yaml = format!(
+            "padding: 4\nborder_size: 2\ninset_size: 2\ntext_size: 16\nlocked_theme: {}\nunlocked_theme: {}\nthemes: {{revealed: {}, failed: {}}}\n",
+            theme("#010101FF"),
+            theme("#020202FF"),
+            theme("#030303FF"),
+            theme("#040404FF"),
+        );
+        let options: TileRenderOptionsBuilder = serde_yaml::from_str(&yaml).unwrap();
+        let themes = options.build().unwrap().themes;
+        let border = |state| themes.get(state).border_color;
+        // `themes` wins over the legacy names, which still fill in what it doesn't give
+        assert_eq!(border(TileState::Revealed), Rgba::new(3, 3, 3, 255));
+        assert_eq!(border(TileState::Completed), Rgba::new(2, 2, 2, 255));
+        assert_eq!(border(TileState::Failed), Rgba::new(4, 4, 4, 255));
+        let defaults = TileThemes::default();
+        assert_eq!(
+            border(TileState::Hidden),
+            defaults.get(TileState::Hidden).border_color
+        );
+        assert_eq!(
+            themes.get(TileState::Failed).image_treatment,
+            ImageTreatment::default_for(TileState::Failed)
+        );
+
+        let tile = |yaml| serde_yaml::from_str::<TileBuilder>(yaml).unwrap().state();
+        assert_eq!(
+            tile("{number: 1, name: A, image: a.png}"),
+            TileState::Revealed
+        );
+        assert_eq!(
+            tile("{number: 1, name: A, image: a.png, unlocked: true}"),
+            TileState::Completed
+        );
+        assert_eq!(
+            tile("{number: 1, name: A, image: a.png, unlocked: false}"),
+            TileState::Revealed
+        );
+        // state wins over unlocked
+        assert_eq!(
+            tile("{number: 1, name: A, image: a.png, state: failed, unlocked: true}"),
+            TileState::Failed
+        );
+    }
+
+    #[test]
+    fn it_parses_tile_themes() {
+        let builder: TileThemeBuilder = serde_yaml::from_str(
+            r##"
+border_color: "#FF0000FF"
+inset_color: "#00FF00"
+background_color: "#0000FFFF"
+text_color: "#FFFFFFFF"
+"##,
+        )
+        .unwrap();
+        let theme = builder.build("themes.failed", TileState::Failed).unwrap();
+        assert_eq!(theme.border_color, Rgba::new(255, 0, 0, 255));
+        assert_eq!(theme.inset_color, Rgba::new(0, 255, 0, 255));
+        // unset colors and treatments fall back to their defaults
+        assert_eq!(theme.progress_bar_color, theme.text_color);
+        assert_eq!(theme.progress_track_color, theme.inset_color);
+        assert_eq!(theme.image_treatment, ImageTreatment::Darken(0.6));
+
+        let builder: TileThemeBuilder = serde_yaml::from_str(
+            r##"
+border_color: "#FF0000FF"
+inset_color: "#00FF00FF"
+background_color: "#0000FFFF"
+text_color: "white"
+progress_bar_color: "#12345"
+image_treatment: {desaturate: 0.5}
+"##,
+        )
+        .unwrap();
+        assert_eq!(
+            builder.image_treatment,
+            Some(ImageTreatment::Desaturate(0.5))
+        );
+        let paths = builder
+            .validate("themes.revealed")
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "tile_render_options.themes.revealed.text_color",
+                "tile_render_options.themes.revealed.progress_bar_color"
+            ]
+        );
+        assert!(matches!(
+            builder.build("themes.revealed", TileState::Revealed),
+            Err(Error::InvalidColor {
+                field: "text_color",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_reports_grids_that_dont_fit() {
+        let content_rect = ContentRect {
+            x1: 0,
+            y1: 0,
+            x2: 3,
+            y2: 100,
+        };
+        // 5 columns don't fit in 3px at any size, so auto comes out as 0 and gets reported
+        let tile_size = resolve_tile_size(
+            TileSize::Auto,
+            &content_rect,
+            (1, 5),
+            (0, 0),
+            Spacing::Between,
+        );
+        assert_eq!(tile_size, 0);
+        let problems = validate_grid(&content_rect, tile_size, 1, 5, (0, 0), Spacing::Between);
+        assert!(matches!(
+            problems[..],
+            [Problem {
+                error: Error::TilesDoNotFit {
+                    axis: Axis::X,
+                    needed: 5,
+                    available: 3,
+                    ..
+                },
+                ..
+            }]
+        ));
+        // absurd sizes are reported rather than overflowing
+        let problems = validate_grid(
+            &content_rect,
+            u32::MAX,
+            2,
+            2,
+            (u32::MAX, u32::MAX),
+            Spacing::Evenly,
+        );
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn it_rejects_a_zero_name_line_limit() {
+        let options: TileRenderOptionsBuilder = serde_yaml::from_str(
+            "padding: 4\nborder_size: 2\ninset_size: 2\ntext_size: 16\nmax_name_lines: 0\n",
+        )
+        .unwrap();
+        let problems = options.validate();
+        assert!(matches!(
+            problems[..],
+            [Problem {
+                error: Error::ZeroMaxNameLines,
+                ..
+            }]
+        ));
+        assert!(matches!(options.build(), Err(Error::ZeroMaxNameLines)));
+    }
+
+    #[test]
+    fn it_blames_the_margin_for_a_derived_content_rect() {
+        let paths = |problems: Vec<Problem>| {
+            problems
+                .into_iter()
+                .map(|problem| problem.path)
+                .collect::<Vec<_>>()
+        };
+        assert!(validate_margin((200, 100), 49, (None, None)).is_empty());
+        let problems = validate_margin((200, 100), 98, (Some(200), Some(100)));
+        assert!(matches!(
+            problems[..],
+            [Problem {
+                error: Error::MarginTooLarge {
+                    axis: Axis::Y,
+                    margin: 98,
+                    size: 100
+                },
+                ..
+            }]
+        ));
+        assert_eq!(paths(problems), ["margin"]);
+        // with the default margin it's the board that's too small
+        let margin = default_margin();
+        assert_eq!(
+            paths(validate_margin((30, 30), margin, (Some(30), None))),
+            ["width", "margin"]
+        );
     }
-    Ok(())
 }
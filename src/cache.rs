@@ -0,0 +1,683 @@
+//! Bookkeeping for the on-disk image cache: where every cached file came from, when it was
+//! fetched and last used, and evicting entries once they get too old or the cache too big.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, DirBuilder},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+/// Lives at the root of the cache directory, next to the cached files
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// Numbers temporary files, so that concurrent writes never share one
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How long cached images stay fresh and how big the cache may get
+#[derive(Debug, Clone, Default)]
+pub struct CacheOptions {
+    /// Entries fetched longer ago than this are revalidated with the server before they're used,
+    /// and entries that haven't been used for this long are removed by `prune`
+    pub ttl: Option<Duration>,
+    /// The least recently used entries are evicted to keep the cache under this many bytes
+    pub max_size: Option<u64>,
+}
+
+/// Everything known about a single cached file. Timestamps are seconds since the unix epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub url: String,
+    pub fetched_at: u64,
+    pub last_accessed: u64,
+    /// Hex encoded SHA-256 of the cached file
+    pub sha256: String,
+    pub size: u64,
+    /// Sniffed from the bytes, or what the server said if that didn't work
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+/// Response headers that are kept so that stale entries can be revalidated
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// A wiki item name resolved to the URL of its image, see the `wiki` module
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ItemEntry {
+    pub url: String,
+    pub resolved_at: u64,
+}
+
+/// What `Cache::prune` removed
+#[derive(Debug, Default)]
+pub struct PruneSummary {
+    /// Keys of every entry that was removed
+    pub removed: Vec<String>,
+    /// Bytes freed on disk
+    pub freed: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    version: u32,
+    /// Keyed by the path of the file relative to the cache directory, with `/` separators
+    entries: BTreeMap<String, CacheEntry>,
+    /// Keyed by item name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    items: BTreeMap<String, ItemEntry>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            entries: BTreeMap::new(),
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+/// The image cache in one directory, with a manifest of what's in it. An [`ImageLoader`]
+/// opens one for its `cache_dir`, see [`ImageLoader::cache`].
+///
+/// [`ImageLoader`]: crate::ImageLoader
+/// [`ImageLoader::cache`]: crate::ImageLoader::cache
+pub struct Cache {
+    dir: PathBuf,
+    options: CacheOptions,
+    manifest: Mutex<Manifest>,
+    /// Set when the manifest has changes that only `flush` writes, so that cache hits don't
+    /// rewrite it every time
+    dirty: AtomicBool,
+}
+
+impl Cache {
+    /// Opens the cache in `dir`, creating it if needed. A missing or unreadable manifest
+    /// starts the cache over, files that are already there get adopted as they're used.
+    pub fn open(dir: impl Into<PathBuf>, options: CacheOptions) -> Result<Self, Error> {
+        let dir = dir.into();
+        let is_new = !dir.exists();
+        DirBuilder::new()
+            .recursive(true)
+            .create(&dir)
+            .map_err(Error::io(&dir))?;
+        let manifest = fs::read(dir.join(MANIFEST_FILE_NAME))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Manifest>(&bytes).ok())
+            .filter(|manifest| manifest.version == MANIFEST_VERSION)
+            .unwrap_or_default();
+        let cache = Self {
+            dir,
+            options,
+            manifest: Mutex::new(manifest),
+            dirty: AtomicBool::new(false),
+        };
+        // a new cache gets a manifest straight away, so that it can be cleared before it's used
+        if is_new {
+            cache.save(&cache.lock())?;
+        }
+        Ok(cache)
+    }
+
+    /// The directory the cache is in
+    pub fn dir(&self) -> &Path {
+        self.dir.as_path()
+    }
+
+    /// Where the file for `key` lives on disk.
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    /// Looks up the entry for `key`, which was downloaded from `url`. Entries whose file has
+    /// gone missing are dropped, and files without an entry (from before there was a manifest)
+    /// are adopted. Neither is written to disk until the next `flush`.
+    pub fn lookup(&self, key: &str, url: &str) -> Result<Option<CacheEntry>, Error> {
+        let path = self.path(key);
+        let mut manifest = self.lock();
+        if !path.is_file() {
+            if manifest.entries.remove(key).is_some() {
+                self.dirty.store(true, Ordering::Relaxed);
+            }
+            return Ok(None);
+        }
+        if let Some(entry) = manifest.entries.get(key) {
+            return Ok(Some(entry.clone()));
+        }
+        let bytes = fs::read(&path).map_err(Error::io(&path))?;
+        let fetched_at = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .map(unix_seconds)
+            .unwrap_or_else(|_| now());
+        let entry = CacheEntry {
+            url: url.to_string(),
+            fetched_at,
+            last_accessed: fetched_at,
+            sha256: sha256_hex(&bytes),
+            size: bytes.len() as u64,
+            content_type: None,
+            etag: None,
+            last_modified: None,
+        };
+        manifest.entries.insert(key.to_string(), entry.clone());
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(Some(entry))
+    }
+
+    /// Whether an entry has outlived the TTL and should be revalidated before it's used.
+    pub fn is_stale(&self, entry: &CacheEntry) -> bool {
+        self.is_expired(entry.fetched_at)
+    }
+
+    /// Looks up what an item name resolved to last time.
+    pub fn item(&self, name: &str) -> Option<ItemEntry> {
+        self.lock().items.get(name).cloned()
+    }
+
+    /// Whether an item has outlived the TTL and should be resolved again before it's used.
+    pub fn is_item_stale(&self, item: &ItemEntry) -> bool {
+        self.is_expired(item.resolved_at)
+    }
+
+    /// Records that an item name resolved to `url`.
+    pub fn store_item(&self, name: &str, url: &str) -> Result<(), Error> {
+        let item = ItemEntry {
+            url: url.to_string(),
+            resolved_at: now(),
+        };
+        let mut manifest = self.lock();
+        manifest.items.insert(name.to_string(), item);
+        self.save(&manifest)
+    }
+
+    /// Records that `key` was just used, in memory until the next `flush`.
+    pub fn touch(&self, key: &str) {
+        self.update(key, |entry| entry.last_accessed = now())
+    }
+
+    /// Records that the server confirmed `key` is still up to date, in memory until the next
+    /// `flush`.
+    pub fn revalidated(&self, key: &str) {
+        self.update(key, |entry| {
+            let now = now();
+            entry.fetched_at = now;
+            entry.last_accessed = now;
+        })
+    }
+
+    /// Writes `bytes` downloaded from `url` to the cache, replacing anything that was there,
+    /// then evicts other entries if the cache has grown past its maximum size.
+    pub fn store(
+        &self,
+        key: &str,
+        url: &str,
+        bytes: &[u8],
+        content_type: Option<String>,
+        validators: Validators,
+    ) -> Result<(), Error> {
+        let path = self.path(key);
+        write_atomically(&path, bytes)?;
+        let now = now();
+        let entry = CacheEntry {
+            url: url.to_string(),
+            fetched_at: now,
+            last_accessed: now,
+            sha256: sha256_hex(bytes),
+            size: bytes.len() as u64,
+            content_type,
+            etag: validators.etag,
+            last_modified: validators.last_modified,
+        };
+        let mut manifest = self.lock();
+        manifest.entries.insert(key.to_string(), entry);
+        if let Some(max_size) = self.options.max_size {
+            self.evict_to_fit(&mut manifest, max_size, Some(key));
+        }
+        self.save(&manifest)
+    }
+
+    /// Every entry, most recently used first.
+    pub fn entries(&self) -> Vec<(String, CacheEntry)> {
+        let mut entries = self
+            .lock()
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_accessed));
+        entries
+    }
+
+    /// Removes entries whose files are gone, entries that haven't been used within the TTL and
+    /// the least recently used entries past the maximum size, along with any temporary files
+    /// left behind by interrupted downloads.
+    pub fn prune(&self) -> Result<PruneSummary, Error> {
+        let mut manifest = self.lock();
+        let mut summary = PruneSummary::default();
+        let dangling = manifest
+            .entries
+            .keys()
+            .filter(|key| !self.path(key).is_file())
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in dangling {
+            manifest.entries.remove(&key);
+            summary.removed.push(key);
+        }
+        if let Some(ttl) = self.options.ttl {
+            let cutoff = now().saturating_sub(ttl.as_secs());
+            let unused = manifest
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.last_accessed < cutoff)
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in unused {
+                if let Some(entry) = manifest.entries.remove(&key) {
+                    let _ = fs::remove_file(self.path(&key));
+                    summary.freed += entry.size;
+                    summary.removed.push(key);
+                }
+            }
+        }
+        if let Some(max_size) = self.options.max_size {
+            let evicted = self.evict_to_fit(&mut manifest, max_size, None);
+            summary.freed += evicted.iter().map(|(_, entry)| entry.size).sum::<u64>();
+            summary
+                .removed
+                .extend(evicted.into_iter().map(|(key, _)| key));
+        }
+        summary.freed += remove_temp_files(&self.dir);
+        self.save(&manifest)?;
+        Ok(summary)
+    }
+
+    /// Deletes every file the manifest lists and then the manifest itself, leaving anything
+    /// else in the directory alone. A directory without a manifest is only cleared if it's
+    /// a cache from before there was one, holding nothing but downloaded images.
+    pub fn clear(&self) -> Result<(), Error> {
+        let mut manifest = self.lock();
+        check_not_current_dir(&self.dir)?;
+        let dir = fs::canonicalize(&self.dir).map_err(Error::io(&self.dir))?;
+        let manifest_path = dir.join(MANIFEST_FILE_NAME);
+        if !manifest_path.is_file() {
+            let Some(files) = legacy_cache_files(&dir) else {
+                return Err(Error::InvalidConfig(format!(
+                    "refusing to clear {}, it has no {MANIFEST_FILE_NAME} and holds more than cached images",
+                    dir.display()
+                )));
+            };
+            for relative in files {
+                fs::remove_file(dir.join(&relative)).map_err(Error::io(dir.join(&relative)))?;
+                remove_empty_parents(&dir, &relative);
+            }
+            *manifest = Manifest::default();
+            self.dirty.store(false, Ordering::Relaxed);
+            return Ok(());
+        }
+        for key in manifest.entries.keys() {
+            // keys are read from disk, so never follow one out of the cache
+            let relative = Path::new(key);
+            if !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            {
+                continue;
+            }
+            if fs::remove_file(dir.join(relative)).is_ok() {
+                remove_empty_parents(&dir, relative);
+            }
+        }
+        fs::remove_file(&manifest_path).map_err(Error::io(&manifest_path))?;
+        // nothing is left to save, not even an empty manifest
+        *manifest = Manifest::default();
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache is at most `max_size` bytes,
+    /// never removing `keep`. Returns what was removed.
+    fn evict_to_fit(
+        &self,
+        manifest: &mut Manifest,
+        max_size: u64,
+        keep: Option<&str>,
+    ) -> Vec<(String, CacheEntry)> {
+        let mut total = manifest.entries.values().map(|e| e.size).sum::<u64>();
+        let mut candidates = manifest
+            .entries
+            .iter()
+            .filter(|(key, _)| Some(key.as_str()) != keep)
+            .map(|(key, entry)| (entry.last_accessed, key.clone()))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        let mut evicted = Vec::new();
+        for (_, key) in candidates {
+            if total <= max_size {
+                break;
+            }
+            if let Some(entry) = manifest.entries.remove(&key) {
+                let _ = fs::remove_file(self.path(&key));
+                total -= entry.size;
+                evicted.push((key, entry));
+            }
+        }
+        evicted
+    }
+
+    fn update(&self, key: &str, f: impl FnOnce(&mut CacheEntry)) {
+        if let Some(entry) = self.lock().entries.get_mut(key) {
+            f(entry);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes the manifest if anything has changed since it was last written. Dropping the
+    /// cache does this too, but can only log a failure.
+    pub fn flush(&self) -> Result<(), Error> {
+        let manifest = self.lock();
+        if self.dirty.load(Ordering::Relaxed) {
+            self.save(&manifest)?;
+        }
+        Ok(())
+    }
+
+    fn is_expired(&self, timestamp: u64) -> bool {
+        match self.options.ttl {
+            Some(ttl) => now().saturating_sub(timestamp) > ttl.as_secs(),
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Manifest> {
+        // a panic while holding the lock can't leave the manifest half-updated, so carry on
+        self.manifest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, manifest: &Manifest) -> Result<(), Error> {
+        let json = serde_json::to_vec_pretty(manifest)
+            .map_err(|source| Error::CacheManifest { source })?;
+        write_atomically(&self.dir.join(MANIFEST_FILE_NAME), &json)?;
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!(error:% = e.display_chain(); "couldn't save the image cache manifest");
+        }
+    }
+}
+
+/// Fails if `dir` is the current directory or one of its parents, which is never somewhere a
+/// cache should be written to or cleared. A directory that doesn't exist yet is fine.
+pub(crate) fn check_not_current_dir(dir: &Path) -> Result<(), Error> {
+    // `.`, `..` and symlinks all have to be resolved to compare against the current directory
+    let Ok(canonical) = fs::canonicalize(dir) else {
+        return Ok(());
+    };
+    let pwd = env::current_dir().map_err(Error::io("."))?;
+    if pwd.starts_with(&canonical) {
+        return Err(Error::InvalidConfig(format!(
+            "the image cache can't be the current directory or one of its parents ({})",
+            canonical.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Writes through a temporary file and renames it into place, so that nobody reading the
+/// cache ever sees a half-written file.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        DirBuilder::new()
+            .recursive(true)
+            .create(parent)
+            .map_err(Error::io(parent))?;
+    }
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp_path, bytes).map_err(Error::io(&temp_path))?;
+    fs::rename(&temp_path, path).map_err(Error::io(path))
+}
+
+/// Every file under `dir` if it looks like a cache from before there was a manifest: nothing
+/// but images, filed at least two directories deep under the parts of their host name. `None`
+/// if there's anything else in there, or nothing at all.
+fn legacy_cache_files(dir: &Path) -> Option<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(parent) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&parent)).ok()? {
+            let entry = entry.ok()?;
+            let relative = parent.join(entry.file_name());
+            let file_type = entry.file_type().ok()?;
+            if file_type.is_dir() {
+                pending.push(relative);
+            } else if file_type.is_file()
+                && relative.components().count() > 2
+                && fs::read(entry.path()).is_ok_and(|bytes| image::guess_format(&bytes).is_ok())
+            {
+                files.push(relative);
+            } else {
+                return None;
+            }
+        }
+    }
+    (!files.is_empty()).then_some(files)
+}
+
+/// Deletes `.tmp` files anywhere under `dir`, returning how many bytes were freed.
+fn remove_temp_files(dir: &Path) -> u64 {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut freed = 0;
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            freed += remove_temp_files(&path);
+        } else if path.extension().is_some_and(|e| e == "tmp") {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            if fs::remove_file(&path).is_ok() {
+                freed += size;
+            }
+        }
+    }
+    freed
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn now() -> u64 {
+    unix_seconds(SystemTime::now())
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a byte count for humans, e.g. 1536 becomes "1.5 KiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// Formats how long ago a unix timestamp was, e.g. "3h ago".
+pub fn format_age(timestamp: u64) -> String {
+    let seconds = now().saturating_sub(timestamp);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3_599 => format!("{}m ago", seconds / 60),
+        3_600..=86_399 => format!("{}h ago", seconds / 3_600),
+        _ => format!("{}d ago", seconds / 86_400),
+    }
+}
+
+/// Removes the directories between `dir` and the file at `relative` that are now empty.
+fn remove_empty_parents(dir: &Path, relative: &Path) {
+    for parent in relative.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() || fs::remove_dir(dir.join(parent)).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn it_only_clears_what_it_cached() {
+        let dir = TempDir::new("clear");
+        fs::write(dir.join("notes.png"), b"not cached").unwrap();
+        // a directory without a manifest that holds anything but images isn't a cache, even
+        // once it's been opened as one
+        let cache = Cache::open(dir.path(), CacheOptions::default()).unwrap();
+        assert!(cache.clear().is_err());
+        assert!(dir.join("notes.png").is_file());
+        // a cache only loses the files it lists
+        let cache_dir = dir.join("cache");
+        let cache = Cache::open(&cache_dir, CacheOptions::default()).unwrap();
+        cache
+            .store(
+                "a/one.png",
+                "a/one.png",
+                b"12345",
+                None,
+                Validators::default(),
+            )
+            .unwrap();
+        fs::write(cache_dir.join("readme.txt"), b"").unwrap();
+        cache.touch("a/one.png");
+        cache.clear().unwrap();
+        drop(cache);
+        assert!(!cache_dir.join("a").exists());
+        assert!(!cache_dir.join(MANIFEST_FILE_NAME).exists());
+        assert!(cache_dir.join("readme.txt").is_file());
+        assert!(dir.join("notes.png").is_file());
+    }
+
+    #[test]
+    fn it_clears_caches_from_before_the_manifest() {
+        const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+        let dir = TempDir::new("legacy");
+        for key in ["example/com/images/helm.png", "i/imgur/com/abc.png"] {
+            let path = dir.join(key);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, PNG_SIGNATURE).unwrap();
+        }
+        // anything that isn't a cached image means it's not just a cache
+        fs::write(dir.join("notes.txt"), b"").unwrap();
+        let cache = Cache::open(dir.path(), CacheOptions::default()).unwrap();
+        assert!(cache.clear().is_err());
+        assert!(dir.join("example/com/images/helm.png").is_file());
+        fs::remove_file(dir.join("notes.txt")).unwrap();
+        cache.clear().unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used_entries() {
+        let dir = TempDir::new("cache");
+        let options = CacheOptions {
+            ttl: None,
+            max_size: Some(10),
+        };
+        let cache = Cache::open(dir.path(), options).expect("expected cache to open");
+        let store = |key: &str| {
+            cache
+                .store(key, key, b"12345", None, Validators::default())
+                .expect("expected store to succeed")
+        };
+        store("a/one.png");
+        store("a/two.png");
+        // make "one" the most recently used, so that "two" is evicted
+        cache.update("a/one.png", |e| e.last_accessed += 10);
+        store("b/three.png");
+        let keys = cache
+            .entries()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains(&"a/one.png".to_string()));
+        assert!(keys.contains(&"b/three.png".to_string()));
+        assert!(!cache.path("a/two.png").exists());
+        // and the manifest survives a reopen
+        let reopened = Cache::open(dir.path(), CacheOptions::default()).unwrap();
+        assert_eq!(reopened.entries().len(), 2);
+    }
+
+    #[test]
+    fn it_saves_hits_in_one_go() {
+        let dir = TempDir::new("flush");
+        let cache = Cache::open(dir.path(), CacheOptions::default()).unwrap();
+        cache
+            .store(
+                "a/one.png",
+                "a/one.png",
+                b"12345",
+                None,
+                Validators::default(),
+            )
+            .unwrap();
+        let saved = || fs::read(dir.join(MANIFEST_FILE_NAME)).unwrap();
+        let before = saved();
+        cache.update("a/one.png", |e| e.last_accessed += 10);
+        assert_eq!(saved(), before);
+        cache.flush().unwrap();
+        assert_ne!(saved(), before);
+        // and whatever hasn't been flushed is saved when the cache goes away
+        cache.update("a/one.png", |e| e.last_accessed += 10);
+        let last_accessed = cache.entries()[0].1.last_accessed;
+        drop(cache);
+        let reopened = Cache::open(dir.path(), CacheOptions::default()).unwrap();
+        assert_eq!(reopened.entries()[0].1.last_accessed, last_accessed);
+    }
+}
@@ -0,0 +1,398 @@
+//! Text drawn around the tiles: a title, subtitle, footer and a legend of tile states, each in
+//! one of the regions between `content_rect` and the edges of the board.
+
+use std::{collections::HashMap, fmt};
+
+use ril::{Image, OverlayMode, Paste, Rgba};
+use serde::Deserialize;
+
+use crate::{
+    palette::TRANSPARENT,
+    text::{TextRenderOptions, TextRenderer},
+    tile::{paste_cropped, render_tile_template, TileState, TileThemes},
+};
+
+/// The largest text size a caption or the legend can be given
+pub const MAX_TEXT_SIZE: u32 = 512;
+/// Captions that don't fit their region are shrunk down to this size before giving up
+const MIN_TEXT_SIZE: f32 = 12.0;
+/// How many lines a caption can wrap onto
+const MAX_LINES: usize = 2;
+/// Space between captions in the same region, and between rows of the legend
+const SPACING: u32 = 8;
+
+/// One of the four areas between `content_rect` and the edges of the board
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Region {
+    /// Above `content_rect`, the full width of the board
+    Top,
+    /// Below `content_rect`, the full width of the board
+    Bottom,
+    /// Left of `content_rect`, as tall as it is
+    Left,
+    /// Right of `content_rect`, as tall as it is
+    Right,
+}
+
+impl Region {
+    pub const ALL: [Region; 4] = [Region::Top, Region::Bottom, Region::Left, Region::Right];
+
+    /// (x, y, width, height) of the region on a board of `dimensions`.
+    pub fn bounds(
+        &self,
+        dimensions: (u32, u32),
+        content_rect: (u32, u32, u32, u32),
+    ) -> (u32, u32, u32, u32) {
+        let (width, height) = dimensions;
+        let (x1, y1, x2, y2) = content_rect;
+        match self {
+            Region::Top => (0, 0, width, y1),
+            Region::Bottom => (0, y2, width, height.saturating_sub(y2)),
+            Region::Left => (0, y1, x1, y2.saturating_sub(y1)),
+            Region::Right => (x2, y1, width.saturating_sub(x2), y2.saturating_sub(y1)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Top => write!(f, "top"),
+            Region::Bottom => write!(f, "bottom"),
+            Region::Left => write!(f, "left"),
+            Region::Right => write!(f, "right"),
+        }
+    }
+}
+
+/// A piece of text such as the title, wrapped and shrunk to fit the width of its region
+#[derive(Debug)]
+pub struct Caption {
+    /// `{team}` is replaced with the name of the team the board is rendered for
+    pub text: String,
+    pub size: u32,
+    pub color: Rgba,
+    pub region: Region,
+}
+
+/// A key to the states of the tiles on the board, each shown as a small tile and a label
+#[derive(Debug)]
+pub struct Legend {
+    pub size: u32,
+    /// Defaults to the text color of each state's theme
+    pub color: Option<Rgba>,
+    pub region: Region,
+    /// Replaces the default labels for these states
+    pub labels: HashMap<TileState, String>,
+}
+
+/// Everything drawn outside `content_rect`, in the order it's stacked within a region
+#[derive(Debug, Default)]
+pub struct Captions {
+    pub title: Option<Caption>,
+    pub subtitle: Option<Caption>,
+    pub legend: Option<Legend>,
+    pub footer: Option<Caption>,
+}
+
+/// What captions are drawn for, besides the captions themselves
+pub struct CaptionContext<'a> {
+    pub dimensions: (u32, u32),
+    pub content_rect: (u32, u32, u32, u32),
+    pub themes: &'a TileThemes,
+    /// The state of every tile, the legend lists each state that appears
+    pub states: &'a [TileState],
+    pub team: Option<&'a str>,
+}
+
+impl Captions {
+    /// Whether there's nothing to draw at all
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.subtitle.is_none()
+            && self.legend.is_none()
+            && self.footer.is_none()
+    }
+
+    /// Draws every caption in its region, one under the other, with the stack centered.
+    pub fn draw(
+        &self,
+        image: &mut Image<Rgba>,
+        text_renderer: &TextRenderer,
+        context: &CaptionContext,
+    ) {
+        for (region, captions) in self.render(text_renderer, context) {
+            let (x, y, width, height) = region.bounds(context.dimensions, context.content_rect);
+            let mut y = y + height.saturating_sub(stack_height(&captions)) / 2;
+            for caption in captions {
+                let x = x + width.saturating_sub(caption.width()) / 2;
+                let caption_height = caption.height();
+                paste_cropped(image, caption, (x, y), width, caption_height);
+                y += caption_height + SPACING;
+            }
+        }
+    }
+
+    /// Regions whose captions don't fit, with the height they need and the height there is.
+    pub fn overflows(
+        &self,
+        text_renderer: &TextRenderer,
+        context: &CaptionContext,
+    ) -> Vec<(Region, u32, u32)> {
+        self.render(text_renderer, context)
+            .into_iter()
+            .filter_map(|(region, captions)| {
+                let (_, _, _, available) = region.bounds(context.dimensions, context.content_rect);
+                let needed = stack_height(&captions);
+                (needed > available).then_some((region, needed, available))
+            })
+            .collect()
+    }
+
+    /// Renders every caption, grouped by the region it goes in.
+    fn render(
+        &self,
+        text_renderer: &TextRenderer,
+        context: &CaptionContext,
+    ) -> Vec<(Region, Vec<Image<Rgba>>)> {
+        Region::ALL
+            .into_iter()
+            .filter_map(|region| {
+                let (_, _, width, _) = region.bounds(context.dimensions, context.content_rect);
+                if width == 0 {
+                    return None;
+                }
+                let title = in_region(&self.title, region);
+                let subtitle = in_region(&self.subtitle, region);
+                let legend = self
+                    .legend
+                    .as_ref()
+                    .filter(|legend| legend.region == region);
+                let footer = in_region(&self.footer, region);
+                let captions = [
+                    title.and_then(|c| c.render(text_renderer, width, context.team)),
+                    subtitle.and_then(|c| c.render(text_renderer, width, context.team)),
+                    legend.and_then(|l| l.render(text_renderer, width, context)),
+                    footer.and_then(|c| c.render(text_renderer, width, context.team)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+                (!captions.is_empty()).then_some((region, captions))
+            })
+            .collect()
+    }
+}
+
+impl Caption {
+    /// `None` when there's nothing to draw, e.g. a caption that's only `{team}` on a board
+    /// that isn't rendered for a team.
+    fn render(
+        &self,
+        text_renderer: &TextRenderer,
+        max_width: u32,
+        team: Option<&str>,
+    ) -> Option<Image<Rgba>> {
+        let text = self.text.replace("{team}", team.unwrap_or_default());
+        if text.trim().is_empty() {
+            return None;
+        }
+        let options = TextRenderOptions {
+            size: self.size as f32,
+            color: self.color,
+            pixelation: None,
+        };
+        let min_size = MIN_TEXT_SIZE.min(options.size);
+        let fitted = text_renderer.fit(&text, max_width, MAX_LINES, min_size, &options);
+        let options = TextRenderOptions {
+            size: fitted.size,
+            ..options
+        };
+        Some(text_renderer.render_lines(&fitted.lines, &options))
+    }
+}
+
+impl Legend {
+    /// The legend's entries laid out in as many rows as it takes to fit in `max_width`, shrunk
+    /// until the widest entry fits on a row of its own.
+    fn render(
+        &self,
+        text_renderer: &TextRenderer,
+        max_width: u32,
+        context: &CaptionContext,
+    ) -> Option<Image<Rgba>> {
+        let states = TileState::ALL
+            .into_iter()
+            .filter(|state| context.states.contains(state))
+            .collect::<Vec<_>>();
+        let min_size = MIN_TEXT_SIZE.min(self.size as f32);
+        let mut size = self.size as f32;
+        while size - 1.0 >= min_size
+            && states
+                .iter()
+                .any(|&state| self.entry_width(text_renderer, state, size) > max_width)
+        {
+            size -= 1.0;
+        }
+        let entries = states
+            .into_iter()
+            .map(|state| self.render_entry(text_renderer, state, context.themes, size))
+            .collect::<Vec<_>>();
+        // entries are as far apart as the text is tall
+        let gap = size as u32;
+        let mut rows: Vec<Vec<Image<Rgba>>> = Vec::new();
+        let mut row_width = 0;
+        for entry in entries {
+            match rows.last_mut() {
+                Some(row) if row_width + gap + entry.width() <= max_width => {
+                    row_width += gap + entry.width();
+                    row.push(entry);
+                }
+                _ => {
+                    row_width = entry.width();
+                    rows.push(vec![entry]);
+                }
+            }
+        }
+        let widths = rows
+            .iter()
+            .map(|row| {
+                let gaps = gap * (row.len() as u32 - 1);
+                row.iter().map(Image::width).sum::<u32>() + gaps
+            })
+            .collect::<Vec<_>>();
+        let width = widths.iter().copied().max()?;
+        let row_height = rows.iter().flatten().map(Image::height).max()?;
+        let height = row_height * rows.len() as u32 + SPACING * (rows.len() as u32 - 1);
+        let mut image = Image::new(width, height, TRANSPARENT);
+        for (i, (row, row_width)) in rows.iter().zip(widths).enumerate() {
+            let mut x = (width - row_width) / 2;
+            let y = i as u32 * (row_height + SPACING);
+            for entry in row {
+                image.draw(&Paste {
+                    position: (x, y),
+                    image: entry,
+                    mask: None,
+                    overlay: Some(OverlayMode::Merge),
+                });
+                x += entry.width() + gap;
+            }
+        }
+        Some(image)
+    }
+
+    /// How wide the entry for `state` is with its label at `size`, see `render_entry`.
+    fn entry_width(&self, text_renderer: &TextRenderer, state: TileState, size: f32) -> u32 {
+        let options = TextRenderOptions {
+            size,
+            ..Default::default()
+        };
+        let (width, height) = text_renderer.measure(self.label(state), &options);
+        let side = height.max(4);
+        side + side / 3 + width
+    }
+
+    /// A tile drawn in the state's theme, as tall as the label next to it.
+    fn render_entry(
+        &self,
+        text_renderer: &TextRenderer,
+        state: TileState,
+        themes: &TileThemes,
+        size: f32,
+    ) -> Image<Rgba> {
+        let theme = themes.get(state);
+        let options = TextRenderOptions {
+            size,
+            color: self.color.unwrap_or(theme.text_color),
+            pixelation: None,
+        };
+        let text = text_renderer.render(self.label(state), &options);
+        let side = text.height().max(4);
+        let border = (side / 10).max(1);
+        let swatch = render_tile_template(
+            side,
+            border,
+            border,
+            theme.background_color,
+            theme.border_color,
+            theme.inset_color,
+        );
+        let padding = side / 3;
+        let mut entry = Image::new(side + padding + text.width(), side, TRANSPARENT);
+        for (position, image) in [((0, 0), &swatch), ((side + padding, 0), &text)] {
+            entry.draw(&Paste {
+                position,
+                image,
+                mask: None,
+                overlay: Some(OverlayMode::Merge),
+            });
+        }
+        entry
+    }
+
+    fn label(&self, state: TileState) -> &str {
+        match self.labels.get(&state) {
+            Some(label) => label,
+            None => state.label(),
+        }
+    }
+}
+
+fn in_region(caption: &Option<Caption>, region: Region) -> Option<&Caption> {
+    caption.as_ref().filter(|caption| caption.region == region)
+}
+
+/// The height of `captions` stacked one under the other.
+fn stack_height(captions: &[Image<Rgba>]) -> u32 {
+    let gaps = SPACING * captions.len().saturating_sub(1) as u32;
+    captions.iter().map(Image::height).sum::<u32>() + gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_lists_only_states_in_use() {
+        let text_renderer = TextRenderer::default();
+        let themes = TileThemes::default();
+        let legend = Legend {
+            size: 16,
+            color: None,
+            region: Region::Bottom,
+            labels: HashMap::new(),
+        };
+        let context = |states| CaptionContext {
+            dimensions: (1000, 600),
+            content_rect: (0, 0, 1000, 500),
+            themes: &themes,
+            states,
+            team: None,
+        };
+        let one = legend.render(&text_renderer, 1000, &context(&[TileState::Completed]));
+        let two = legend.render(
+            &text_renderer,
+            1000,
+            &context(&[TileState::Completed, TileState::Failed]),
+        );
+        let (one, two) = (one.unwrap(), two.unwrap());
+        assert!(two.width() > one.width());
+        assert_eq!(two.height(), one.height());
+        // a narrow region puts each entry on its own row
+        let narrow = legend.render(
+            &text_renderer,
+            one.width(),
+            &context(&[TileState::Completed, TileState::Failed]),
+        );
+        assert!(narrow.unwrap().height() > one.height());
+        assert!(legend.render(&text_renderer, 1000, &context(&[])).is_none());
+        // entries too wide for the region on their own are shrunk to fit
+        let big = Legend {
+            size: 200,
+            ..legend
+        };
+        let shrunk = big.render(&text_renderer, 300, &context(&[TileState::Completed]));
+        assert!(shrunk.unwrap().width() <= 300);
+    }
+}
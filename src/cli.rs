@@ -0,0 +1,329 @@
+//! Command-line argument definitions.
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use ril::ImageFormat;
+use url::Url;
+
+use boardbuilder::{images::DEFAULT_S3_ENDPOINT, wiki::DEFAULT_WIKI_API_URL};
+
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about = "Builds bingo board images from YAML board definitions"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct GlobalArgs {
+    /// Directory used to cache downloaded images
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        default_value = ".cache/images"
+    )]
+    pub cache_dir: PathBuf,
+
+    /// Most images to download at once
+    #[arg(long, global = true, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_fetches: u16,
+
+    /// Give up on a download after this long, e.g. 30s or 2m
+    #[arg(long, global = true, value_name = "DURATION", default_value = "30s", value_parser = parse_duration)]
+    pub timeout: Duration,
+
+    /// Retry downloads that time out or get a 429 or 5xx response this many times
+    #[arg(long, global = true, value_name = "N", default_value_t = 3)]
+    pub retries: u32,
+
+    /// Most requests per second to any one host, 0 for no limit
+    #[arg(long, global = true, value_name = "N", default_value_t = 4)]
+    pub rate_limit: u32,
+
+    /// Revalidate cached images older than this with the server, e.g. 12h or 7d
+    #[arg(long, global = true, value_name = "AGE", value_parser = parse_duration)]
+    pub cache_ttl: Option<Duration>,
+
+    /// Evict the least recently used images to keep the cache under this size, e.g. 500M
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    pub cache_max_size: Option<u64>,
+
+    /// Only use cached images, failing if any would have to be downloaded
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// MediaWiki API used to look up the images of tiles that give an `item`
+    #[arg(long, global = true, value_name = "URL", default_value = DEFAULT_WIKI_API_URL)]
+    pub wiki_api_url: Url,
+
+    /// S3-compatible endpoint that `s3:<bucket>/<key>` images are fetched from
+    #[arg(long, global = true, value_name = "URL", default_value = DEFAULT_S3_ENDPOINT)]
+    pub s3_endpoint: Url,
+
+    /// Print more information about what's happening; repeat for even more
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// How to write log messages to stderr
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render a board YAML file to an image
+    Render(RenderArgs),
+    /// Render one image per team from a board and a directory of progress files
+    Batch(BatchArgs),
+    /// Compute completed lines and points for a board
+    Score(ScoreArgs),
+    /// Check a board YAML file for problems without downloading or rendering anything
+    Validate(ValidateArgs),
+    /// Download every image a board needs into the cache ahead of time
+    Prefetch(PrefetchArgs),
+    /// Inspect or manage the image cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Write a starter board YAML file
+    Init(InitArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Board definition to render
+    pub input: PathBuf,
+
+    /// Where to write the rendered image
+    pub output: PathBuf,
+
+    /// Output image format; inferred from the output extension when omitted
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Team progress file to apply to the board before rendering
+    #[arg(short, long, value_name = "FILE")]
+    pub progress: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// Board definition shared by every team
+    pub input: PathBuf,
+
+    /// Directory of team progress YAML files
+    pub progress_dir: PathBuf,
+
+    /// Directory to write one image per progress file into
+    pub output_dir: PathBuf,
+
+    /// Output image format
+    #[arg(short, long, value_enum, default_value = "png")]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    /// Board definition to validate
+    pub input: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct ScoreArgs {
+    /// Board definition to score
+    pub input: PathBuf,
+
+    /// Team progress file to score instead of the board's own tile states
+    #[arg(short, long, value_name = "FILE")]
+    pub progress: Option<PathBuf>,
+
+    /// How to print the score
+    #[arg(short, long, value_enum, default_value = "text")]
+    pub format: ReportFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct PrefetchArgs {
+    /// Board definition whose images should be cached
+    pub input: PathBuf,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Print the cache directory
+    Dir,
+    /// List cached images, most recently used first
+    List,
+    /// Remove images that haven't been used within --cache-ttl and evict down to --cache-max-size
+    Prune,
+    /// Delete every cached image
+    Clear,
+    /// Check cached images with the server now and download them again if they've changed
+    Refresh {
+        /// URLs of the images to refresh
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        urls: Vec<String>,
+        /// Refresh every cached image
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// Where to write the board definition
+    #[arg(default_value = "board.yaml")]
+    pub output: PathBuf,
+
+    /// Overwrite the output file if it already exists
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Gif,
+}
+
+impl From<OutputFormat> for ImageFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Gif => ImageFormat::Gif,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl GlobalArgs {
+    /// Errors only with `-q`, info by default, then debug and trace for each `-v`
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Gif => "gif",
+        }
+    }
+}
+
+impl RenderArgs {
+    /// The format to encode the output with, falling back to PNG if neither the flag nor the
+    /// output extension say otherwise.
+    pub fn output_format(&self) -> ImageFormat {
+        if let Some(format) = self.format {
+            return format.into();
+        }
+        match ImageFormat::from_path(&self.output) {
+            Ok(ImageFormat::Unknown) | Err(_) => ImageFormat::Png,
+            Ok(format) => format,
+        }
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `12h` or `7d`; plain numbers are seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<u64>().map_err(|_| {
+        format!(
+            "expected a number followed by s, m, h or d, got {:?}",
+            value
+        )
+    })?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {:?}, expected s, m, h or d", unit)),
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{:?} is too long", value))
+}
+
+/// Parses sizes like `500K`, `200M` or `1G` (powers of 1024, a trailing `B` is optional);
+/// plain numbers are bytes.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("expected a number followed by K, M or G, got {:?}", value))?;
+    let multiplier = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("unknown unit {:?}, expected K, M or G", unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{:?} is too large", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_durations_and_sizes() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("7w").is_err());
+        assert_eq!(parse_size("500K"), Ok(500 << 10));
+        assert_eq!(parse_size("1gb"), Ok(1 << 30));
+        // too big for a u64 once the unit is applied
+        assert!(parse_duration("999999999999999999d").is_err());
+        assert!(parse_size("99999999999999999G").is_err());
+        let cli = Cli::try_parse_from(["boardbuilder", "--cache-max-size", "99999999999G", "init"]);
+        assert!(cli.is_err());
+    }
+}
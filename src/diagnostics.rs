@@ -0,0 +1,445 @@
+//! Collects problems found in a board definition and reports them against the YAML source.
+
+use std::{collections::HashMap, fmt::Write};
+
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+use crate::error::Error;
+
+/// The code of [`Error::Yaml`], for diagnostics made straight from serde's errors
+const YAML_ERROR_CODE: &str = "E0501";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn label(&self) -> &'static str {
+        match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A location in the YAML source; `line` and `column` are 1-based, `len` is in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+/// One problem with a board definition, and where in it the problem is
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The code of the error this came from, see [`Error::code`]
+    pub code: Option<&'static str>,
+    pub message: String,
+    /// Dotted path of the offending value, e.g. `tiles[3].number`
+    pub path: Option<String>,
+    /// Explicit location, takes priority over `path`
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl ToString) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.to_string(),
+            path: None,
+            span: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl ToString) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn note(message: impl ToString) -> Self {
+        Self::new(Severity::Note, message)
+    }
+
+    /// An error diagnostic with the error's code and its whole cause chain as the message.
+    pub fn from_error(error: &Error) -> Self {
+        Self {
+            code: Some(error.code()),
+            ..Self::error(error.display_chain())
+        }
+    }
+
+    /// Points at a field by its path, like `tiles[3].image`
+    pub fn at(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Points at a range of the YAML source
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Adds a note that's printed after the message
+    pub fn with_note(mut self, note: impl ToString) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Converts a deserialization failure into a diagnostic pointing at where it happened.
+    pub fn from_yaml_error(error: &serde_yaml::Error) -> Self {
+        let diagnostic = Self {
+            code: Some(YAML_ERROR_CODE),
+            ..Self::error(error)
+        };
+        match error.location() {
+            Some(location) => diagnostic.with_span(Span {
+                line: location.line(),
+                column: location.column(),
+                len: 1,
+            }),
+            None => diagnostic,
+        }
+    }
+}
+
+/// Maps dotted paths in a YAML document to the location of their values.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    spans: HashMap<String, Span>,
+}
+
+impl SourceMap {
+    /// Indexes `source`; a document that fails to parse yields an empty (but usable) map.
+    pub fn parse(source: &str) -> Self {
+        let mut indexer = Indexer {
+            source,
+            stack: Vec::new(),
+            spans: HashMap::new(),
+        };
+        // errors here are reported by serde when the document is deserialized
+        let _ = Parser::new_from_str(source).load(&mut indexer, false);
+        Self {
+            spans: indexer.spans,
+        }
+    }
+
+    /// Finds the span of `path`, falling back to the closest parent that has one.
+    pub fn span(&self, path: &str) -> Option<Span> {
+        let mut path = path;
+        loop {
+            if let Some(span) = self.spans.get(path) {
+                return Some(*span);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+enum Frame {
+    Mapping {
+        path: String,
+        key: Option<(String, Span)>,
+    },
+    Sequence {
+        path: String,
+        index: usize,
+    },
+}
+
+struct Indexer<'a> {
+    source: &'a str,
+    stack: Vec<Frame>,
+    spans: HashMap<String, Span>,
+}
+
+impl Indexer<'_> {
+    fn span_at(&self, mark: &Marker, scalar: Option<&str>) -> Span {
+        // scalars are underlined up to the end of the token; a rough cut is good enough here
+        let line = self
+            .source
+            .lines()
+            .nth(mark.line().saturating_sub(1))
+            .unwrap_or("");
+        let rest = line.chars().skip(mark.col()).collect::<String>();
+        let len = match (rest.chars().next(), scalar) {
+            (Some(quote @ ('"' | '\'')), _) => rest[1..].find(quote).map(|i| i + 2),
+            (_, Some(value)) if !value.is_empty() && rest.starts_with(value) => {
+                Some(value.chars().count())
+            }
+            _ => rest
+                .find([',', '}', ']', '#'])
+                .map(|i| rest[..i].trim_end().len()),
+        }
+        .unwrap_or(rest.trim_end().len());
+        Span {
+            line: mark.line(),
+            column: mark.col() + 1,
+            len: len.max(1),
+        }
+    }
+
+    /// Called when a node starts; returns the path of the node and records its span when it is
+    /// in value position. Keys return `None`.
+    fn enter_node(&mut self, span: Span, scalar: Option<&str>) -> Option<String> {
+        let path = match self.stack.last_mut() {
+            None => String::new(),
+            Some(Frame::Sequence { path, index }) => {
+                let child = format!("{}[{}]", path, index);
+                *index += 1;
+                child
+            }
+            Some(Frame::Mapping { path, key }) => match key.take() {
+                None => {
+                    *key = scalar.map(|s| (s.to_string(), span));
+                    return None;
+                }
+                Some((key, key_span)) => {
+                    let child = if path.is_empty() {
+                        key
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    // containers are easier to spot by their key than their first child
+                    let span = if scalar.is_some() { span } else { key_span };
+                    self.spans.insert(child.clone(), span);
+                    return Some(child);
+                }
+            },
+        };
+        self.spans.insert(path.clone(), span);
+        Some(path)
+    }
+}
+
+impl MarkedEventReceiver for Indexer<'_> {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        let scalar = match &ev {
+            Event::Scalar(value, ..) => Some(value.as_str()),
+            _ => None,
+        };
+        let span = self.span_at(&mark, scalar);
+        match ev {
+            Event::Scalar(value, ..) => {
+                self.enter_node(span, Some(&value));
+            }
+            Event::Alias(..) => {
+                self.enter_node(span, Some(""));
+            }
+            Event::MappingStart(..) => {
+                let path = self.enter_node(span, None).unwrap_or_default();
+                self.stack.push(Frame::Mapping { path, key: None });
+            }
+            Event::SequenceStart(..) => {
+                let path = self.enter_node(span, None).unwrap_or_default();
+                self.stack.push(Frame::Sequence { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Renders diagnostics rustc-style, quoting the offending line of the source.
+pub struct Report<'a> {
+    file_name: &'a str,
+    source: &'a str,
+    source_map: SourceMap,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(file_name: &'a str, source: &'a str) -> Self {
+        Self {
+            file_name,
+            source,
+            source_map: SourceMap::parse(source),
+        }
+    }
+
+    /// Formats one diagnostic, quoting the line it points at when that can be found
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let _ = match diagnostic.code {
+            Some(code) => writeln!(
+                out,
+                "{}[{}]: {}",
+                diagnostic.severity.label(),
+                code,
+                diagnostic.message
+            ),
+            None => writeln!(
+                out,
+                "{}: {}",
+                diagnostic.severity.label(),
+                diagnostic.message
+            ),
+        };
+        let span = self.span(diagnostic);
+        let gutter = span.map(|s| s.line.to_string().len()).unwrap_or(1);
+        let pad = " ".repeat(gutter);
+        match span {
+            Some(span) => {
+                let _ = writeln!(
+                    out,
+                    "{}--> {}:{}:{}",
+                    pad, self.file_name, span.line, span.column
+                );
+                let line = self.source.lines().nth(span.line - 1).unwrap_or("");
+                let _ = writeln!(out, "{} |", pad);
+                let _ = writeln!(out, "{} | {}", span.line, line);
+                let _ = writeln!(
+                    out,
+                    "{} | {}{}",
+                    pad,
+                    " ".repeat(span.column - 1),
+                    "^".repeat(span.len)
+                );
+            }
+            None => {
+                let _ = writeln!(out, "{}--> {}", pad, self.file_name);
+            }
+        }
+        for note in diagnostic.notes.iter() {
+            let _ = writeln!(out, "{} = note: {}", pad, note);
+        }
+        out
+    }
+
+    /// Where in the source `diagnostic` points, if anywhere.
+    pub fn span(&self, diagnostic: &Diagnostic) -> Option<Span> {
+        diagnostic.span.or_else(|| {
+            diagnostic
+                .path
+                .as_deref()
+                .and_then(|path| self.source_map.span(path))
+        })
+    }
+
+    /// The name the file is reported under
+    pub fn file_name(&self) -> &str {
+        self.file_name
+    }
+
+    /// One-line summary of how many errors and warnings were emitted.
+    pub fn summary(&self, diagnostics: &[Diagnostic]) -> Option<String> {
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|d| d.severity == severity)
+                .count()
+        };
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let errors = count(Severity::Error);
+        let warnings = count(Severity::Warning);
+        match (errors, warnings) {
+            (0, 0) => None,
+            (0, w) => Some(format!("{} warning{} emitted", w, plural(w))),
+            (e, 0) => Some(format!("{} has {} error{}", self.file_name, e, plural(e))),
+            (e, w) => Some(format!(
+                "{} has {} error{}; {} warning{} emitted",
+                self.file_name,
+                e,
+                plural(e),
+                w,
+                plural(w)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+rows: 1
+content_rect:
+  x1: 20
+tiles:
+  - number: 1
+    name: \"Serpentine helm\"
+  - {number: 3, name: Mark}
+";
+
+    #[test]
+    fn it_maps_paths_to_spans() {
+        let map = SourceMap::parse(SOURCE);
+        let span = |path| map.span(path).expect("expected a span");
+        assert_eq!(
+            span("rows"),
+            Span {
+                line: 1,
+                column: 7,
+                len: 1
+            }
+        );
+        assert_eq!(
+            span("content_rect.x1"),
+            Span {
+                line: 3,
+                column: 7,
+                len: 2
+            }
+        );
+        assert_eq!(
+            span("content_rect"),
+            Span {
+                line: 2,
+                column: 1,
+                len: 12
+            }
+        );
+        assert_eq!(
+            span("tiles[0].name"),
+            Span {
+                line: 6,
+                column: 11,
+                len: 17
+            }
+        );
+        assert_eq!(
+            span("tiles[1].number"),
+            Span {
+                line: 7,
+                column: 14,
+                len: 1
+            }
+        );
+        // falls back to the parent for paths that aren't in the document
+        assert_eq!(span("tiles[1].image"), span("tiles[1]"));
+    }
+
+    #[test]
+    fn it_renders_rustc_style() {
+        let report = Report::new("board.yaml", SOURCE);
+        let diagnostic = Diagnostic::error("unexpected tile number 3")
+            .at("tiles[1].number")
+            .with_note("tiles are numbered 1 to 2");
+        let expected = "\
+error: unexpected tile number 3
+ --> board.yaml:7:14
+  |
+7 |   - {number: 3, name: Mark}
+  |              ^
+  = note: tiles are numbered 1 to 2
+";
+        assert_eq!(report.render(&diagnostic), expected);
+    }
+}
@@ -1,26 +1,351 @@
+//! The one error type everything in boardbuilder returns. Every variant carries what it's
+//! about (the tile, URL or file) and has a stable code, so that callers can match on failures
+//! without parsing messages. Messages don't repeat their cause, [`Error::display_chain`] and
+//! [`std::error::Error::source`] give the whole story.
+
+use std::{collections::BTreeSet, fmt, path::PathBuf};
+
 use thiserror::Error;
 
+/// Variants are added as boardbuilder grows, and some only exist with the `network` feature,
+/// so matches need a wildcard arm; [`Error::code`] is stable across both.
 #[derive(Debug, Error)]
-pub enum AppError {
-    #[error("Invalid configuration: {0}")]
-    InvalidConfigError(&'static str),
+#[non_exhaustive]
+pub enum Error {
+    // configuration and image locations, E01xx
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("invalid image location {location:?}: {reason}")]
+    InvalidLocation { location: String, reason: String },
+
+    // the filesystem and the image cache, E02xx
+    #[error("couldn't access {}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("couldn't write the image cache manifest")]
+    CacheManifest { source: serde_json::Error },
+
+    // downloads, E03xx
+    #[cfg(feature = "network")]
+    #[error("couldn't download {url}")]
+    Http { url: String, source: reqwest::Error },
+
+    /// A response that wasn't a success, after any retries
+    #[cfg(feature = "network")]
+    #[error("{url} responded with {status}")]
+    HttpStatus {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+
+    /// An image that isn't cached and would have to be downloaded
+    #[error("can't download {url}, boardbuilder was built without the network feature")]
+    NetworkDisabled { url: String },
+
+    /// Images that would have to be downloaded while offline
+    #[error("{} not in the image cache and offline mode is on: {}", if .locations.len() == 1 { "image is" } else { "images are" }, .locations.join(", "))]
+    NotCached { locations: Vec<String> },
+
+    /// An item the wiki doesn't have an image for
+    #[error("no image found on the wiki for item {item:?}")]
+    ItemNotFound { item: String },
+
+    #[error("unexpected response from the wiki API")]
+    WikiResponse { source: serde_json::Error },
+
+    // decoding and drawing images, E04xx
+    #[error("couldn't decode the image")]
+    Decode { source: image::ImageError },
+
+    /// ril's errors don't implement `std::error::Error`, so they can only be kept as text
+    #[error("image processing failed: {reason}")]
+    Ril { reason: String },
+
+    #[error("couldn't write {}: {reason}", .path.display())]
+    Save { path: PathBuf, reason: String },
+
+    /// Wraps the error for one of several images loaded at once, to say which one failed
+    #[error("couldn't load {location}")]
+    Load {
+        location: String,
+        source: Box<Error>,
+    },
+
+    /// An image the board needs that couldn't be loaded; `tiles` is empty for the background
+    #[error("couldn't load the image for {} from {location}", TileList(.tiles))]
+    ImageFailed {
+        tiles: Vec<u8>,
+        location: String,
+        source: Box<Error>,
+    },
+
+    // board and progress files, E05xx
+    #[error("invalid YAML")]
+    Yaml { source: serde_yaml::Error },
+
+    #[error("wrong number of tiles: expected {expected:?}, actual {actual:?}")]
+    WrongNumberOfTiles { expected: usize, actual: usize },
+
+    #[error("tiles must be consecutively numbered, missing {0:?}")]
+    MissingTiles(BTreeSet<u8>),
+
+    #[error("tile number {number} is out of range, tiles must be numbered 1 to {count}")]
+    UnexpectedTile { number: u8, count: usize },
+
+    #[error("tile number {number} is used more than once, first by tiles[{first}]")]
+    DuplicateTile { number: u8, first: usize },
+
+    #[error("content_rect.{axis}1 ({start}) must be less than content_rect.{axis}2 ({end})")]
+    InvertedContentRect { axis: Axis, start: u32, end: u32 },
+
+    #[error(
+        "content_rect.{axis}2 ({end}) is {}px outside the {limit}px {} of the board",
+        .end - .limit,
+        .axis.dimension()
+    )]
+    ContentRectOutOfBounds { axis: Axis, end: u32, limit: u32 },
+
+    #[error(
+        "{count} tiles of {tile_size}px {}need {needed}px but content_rect is only {available}px in {}, {}px short",
+        if *.gap > 0 { format!("and their {}px gaps ", .gap) } else { String::new() },
+        .axis.dimension(),
+        .needed - .available
+    )]
+    TilesDoNotFit {
+        axis: Axis,
+        count: usize,
+        tile_size: u32,
+        gap: u32,
+        /// The tiles and their gaps together
+        needed: u32,
+        available: u32,
+    },
+
+    #[error(
+        "tiles of {tile_size}px have no room inside their {}px of border, inset and padding on each side",
+        .border + .inset + .padding
+    )]
+    TileTooSmall {
+        tile_size: u32,
+        border: u32,
+        inset: u32,
+        padding: u32,
+    },
+
+    #[error("tile {number} has no image, it needs an image, images, an item or items")]
+    MissingImage { number: u8 },
+
+    #[error("tile {number} sets both image and item, only one is allowed")]
+    ImageAndItem { number: u8 },
 
-    #[error("Argument '{0}' is invalid - details: {1}")]
-    InvalidArgumentError(&'static str, &'static str),
+    #[error("tile {number} has a progress target of 0, it must be at least 1")]
+    ZeroProgressTarget { number: u8 },
 
-    #[error(transparent)]
-    FilesystemError(std::io::Error),
+    #[error("invalid color {value:?} for {theme}.{field}: {reason}")]
+    InvalidColor {
+        theme: &'static str,
+        field: &'static str,
+        value: String,
+        reason: String,
+    },
 
-    #[error(transparent)]
-    UrlParseError(url::ParseError),
+    #[error("max_scale must be greater than 0, got {value}")]
+    InvalidMaxScale { value: f32 },
 
-    #[error(transparent)]
-    HttpError(reqwest::Error),
+    #[error("max_name_lines must be at least 1")]
+    ZeroMaxNameLines,
+
+    #[error("{caption}.size must be between 1 and {max}, got {size}")]
+    InvalidCaptionSize {
+        caption: &'static str,
+        size: u32,
+        max: u32,
+    },
+
+    /// A board or progress file with problems, which have been reported as diagnostics
+    #[error("{} has {errors} error{}", .path.display(), if *.errors == 1 { "" } else { "s" })]
+    InvalidFile { path: PathBuf, errors: usize },
+
+    #[error("{} must be set when the board has no image", .axis.dimension())]
+    MissingCanvasSize { axis: Axis },
+
+    #[error(
+        "a margin of {margin}px on each side leaves no room in the {size}px {} of the board",
+        .axis.dimension()
+    )]
+    MarginTooLarge { axis: Axis, margin: u32, size: u32 },
+
+    // the command line, E06xx
+    #[error("no progress files (*.yaml, *.yml) found in {}", .dir.display())]
+    NoProgressFiles { dir: PathBuf },
+
+    #[error("failed to fetch {failed} of {total} images")]
+    PrefetchFailed { failed: usize, total: usize },
+
+    #[error("failed to refresh {failed} of {total} images")]
+    RefreshFailed { failed: usize, total: usize },
+
+    #[error("{} already exists, pass --force to overwrite it", .path.display())]
+    FileExists { path: PathBuf },
+
+    #[error(
+        "{} and {} would both be written to {}, rename one of them",
+        .first.display(),
+        .second.display(),
+        .output.display()
+    )]
+    DuplicateOutput {
+        first: PathBuf,
+        second: PathBuf,
+        output: PathBuf,
+    },
+}
+
+impl Error {
+    /// A code that identifies the kind of error and won't change between versions, e.g.
+    /// `E0301`. The second digit is the area: configuration, files, downloads, images, boards
+    /// and the command line.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidConfig(_) => "E0101",
+            Error::InvalidLocation { .. } => "E0102",
+            Error::Io { .. } => "E0201",
+            Error::CacheManifest { .. } => "E0202",
+            #[cfg(feature = "network")]
+            Error::Http { .. } => "E0301",
+            #[cfg(feature = "network")]
+            Error::HttpStatus { .. } => "E0302",
+            Error::NetworkDisabled { .. } => "E0303",
+            Error::NotCached { .. } => "E0304",
+            Error::ItemNotFound { .. } => "E0305",
+            Error::WikiResponse { .. } => "E0306",
+            Error::Decode { .. } => "E0401",
+            Error::Ril { .. } => "E0402",
+            Error::Save { .. } => "E0403",
+            Error::Load { .. } => "E0404",
+            Error::ImageFailed { .. } => "E0405",
+            Error::Yaml { .. } => "E0501",
+            Error::WrongNumberOfTiles { .. } => "E0502",
+            Error::MissingTiles(_) => "E0503",
+            Error::UnexpectedTile { .. } => "E0504",
+            Error::DuplicateTile { .. } => "E0505",
+            Error::InvertedContentRect { .. } => "E0506",
+            Error::ContentRectOutOfBounds { .. } => "E0507",
+            Error::TilesDoNotFit { .. } => "E0508",
+            Error::MissingImage { .. } => "E0509",
+            Error::ImageAndItem { .. } => "E0510",
+            Error::ZeroProgressTarget { .. } => "E0511",
+            Error::InvalidColor { .. } => "E0512",
+            Error::InvalidMaxScale { .. } => "E0513",
+            Error::InvalidFile { .. } => "E0514",
+            Error::MissingCanvasSize { .. } => "E0515",
+            Error::TileTooSmall { .. } => "E0516",
+            Error::InvalidCaptionSize { .. } => "E0517",
+            Error::ZeroMaxNameLines => "E0518",
+            Error::MarginTooLarge { .. } => "E0519",
+            Error::NoProgressFiles { .. } => "E0601",
+            Error::PrefetchFailed { .. } => "E0602",
+            Error::RefreshFailed { .. } => "E0603",
+            Error::FileExists { .. } => "E0604",
+            Error::DuplicateOutput { .. } => "E0605",
+        }
+    }
+
+    /// The message followed by every cause, on one line.
+    pub fn display_chain(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            message.push_str(": ");
+            message.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        message
+    }
+
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Error {
+        let path = path.into();
+        move |source| Error::Io { path, source }
+    }
+
+    pub fn invalid_location(location: &str, reason: impl Into<String>) -> Error {
+        Error::InvalidLocation {
+            location: location.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl From<ril::Error> for Error {
+    fn from(error: ril::Error) -> Self {
+        Error::Ril {
+            reason: error.to_string(),
+        }
+    }
+}
+
+/// Which dimension of the board a size problem is along
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    pub fn dimension(&self) -> &'static str {
+        match self {
+            Axis::X => "width",
+            Axis::Y => "height",
+        }
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::X => write!(f, "x"),
+            Axis::Y => write!(f, "y"),
+        }
+    }
+}
+
+/// "the background", "tile 3" or "tiles 3, 5"
+struct TileList<'a>(&'a [u8]);
+
+impl fmt::Display for TileList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [] => write!(f, "the background"),
+            [number] => write!(f, "tile {}", number),
+            numbers => {
+                let numbers = numbers.iter().map(u8::to_string).collect::<Vec<_>>();
+                write!(f, "tiles {}", numbers.join(", "))
+            }
+        }
+    }
+}
 
-    #[error(transparent)]
-    ImageError(image::ImageError),
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    /// An error surfaced from the RIL image processing library
-    #[error("RIL error: {0}")]
-    RILError(ril::Error),
+    #[test]
+    fn it_chains_causes() {
+        let error = Error::ImageFailed {
+            tiles: vec![4, 6],
+            location: "https://example.com/a.png".to_string(),
+            source: Box::new(Error::ItemNotFound {
+                item: "Twisted bow".to_string(),
+            }),
+        };
+        assert_eq!(error.code(), "E0405");
+        assert_eq!(
+            error.display_chain(),
+            "couldn't load the image for tiles 4, 6 from https://example.com/a.png: no image found on the wiki for item \"Twisted bow\""
+        );
+    }
 }
@@ -0,0 +1,265 @@
+//! The HTTP client shared by everything that downloads: timeouts, retries with exponential
+//! backoff for transient failures, and a per-host rate limit so that boards with a lot of
+//! images don't hammer the wiki.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{trace, warn};
+use reqwest::{
+    blocking::{Client, ClientBuilder, Response},
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use url::Url;
+
+use crate::error::Error;
+
+/// The longest a single wait between attempts can be, whatever the server asks for
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Timeouts, retries and rate limiting for every download
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// How long a whole request, including reading the body, may take
+    pub timeout: Duration,
+    /// How many times a request is retried after a timeout, a 429 or a 5xx
+    pub retries: u32,
+    /// The wait before the first retry, doubled for each one after
+    pub backoff: Duration,
+    /// Most requests per second to any one host
+    pub rate_limit: Option<u32>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            rate_limit: Some(4),
+        }
+    }
+}
+
+/// A response that has been read in full
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// A blocking HTTP client that retries transient failures and rate limits each host
+pub struct HttpClient {
+    client: Client,
+    options: HttpOptions,
+    /// When the next request to each host may start
+    next_request: Mutex<HashMap<String, Instant>>,
+}
+
+impl HttpClient {
+    pub fn new(options: HttpOptions) -> Result<Self, Error> {
+        let client = ClientBuilder::new()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(options.timeout)
+            .build()
+            .map_err(|e| Error::InvalidConfig(format!("couldn't set up the HTTP client: {}", e)))?;
+        Ok(Self {
+            client,
+            options,
+            next_request: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// GETs `url` and reads the body, retrying transient failures, including connections
+    /// that drop partway through the body. Responses other than 2xx (and 304, which only
+    /// comes back for conditional requests) are errors.
+    pub fn get(&self, url: &Url, headers: HeaderMap) -> Result<HttpResponse, Error> {
+        let mut attempt = 0;
+        loop {
+            self.wait_for_turn(url);
+            trace!(url = url.as_str(), attempt; "sending request");
+            let result = self.client.get(url.clone()).headers(headers.clone()).send();
+            let (reason, retry_after) = match result {
+                Ok(response)
+                    if response.status().is_success()
+                        || response.status() == StatusCode::NOT_MODIFIED =>
+                {
+                    match read(response) {
+                        Ok(response) => return Ok(response),
+                        Err(e) if is_retryable(&e) && attempt < self.options.retries => {
+                            (e.to_string(), None)
+                        }
+                        Err(source) => {
+                            return Err(Error::Http {
+                                url: url.to_string(),
+                                source,
+                            })
+                        }
+                    }
+                }
+                Ok(response)
+                    if is_transient(response.status()) && attempt < self.options.retries =>
+                {
+                    (
+                        response.status().to_string(),
+                        retry_after(response.headers()),
+                    )
+                }
+                Ok(response) => {
+                    return Err(Error::HttpStatus {
+                        url: url.to_string(),
+                        status: response.status(),
+                    });
+                }
+                Err(e) if is_retryable(&e) && attempt < self.options.retries => {
+                    (e.to_string(), None)
+                }
+                Err(source) => {
+                    return Err(Error::Http {
+                        url: url.to_string(),
+                        source,
+                    })
+                }
+            };
+            let backoff = self.options.backoff * 2u32.saturating_pow(attempt);
+            let delay = retry_after.unwrap_or(backoff).min(MAX_RETRY_DELAY);
+            warn!(
+                url = url.as_str(), reason = reason.as_str(), delay_ms = delay.as_millis() as u64;
+                "retrying request"
+            );
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Blocks until the rate limit allows another request to the host of `url`.
+    fn wait_for_turn(&self, url: &Url) {
+        let Some(rate_limit) = self.options.rate_limit.filter(|&r| r > 0) else {
+            return;
+        };
+        let interval = Duration::from_secs(1) / rate_limit;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let wait = {
+            let mut next_request = self.next_request.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let next = next_request.entry(host).or_insert(now);
+            let start = (*next).max(now);
+            *next = start + interval;
+            start - now
+        };
+        thread::sleep(wait);
+    }
+}
+
+fn read(response: Response) -> reqwest::Result<HttpResponse> {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes()?.to_vec();
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// Whether a failed request is worth retrying: timeouts, failed connections and connections
+/// that dropped while the body was being read.
+fn is_retryable(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_body()
+}
+
+/// Whether a status is worth retrying: rate limiting and server errors.
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Reads `Retry-After`, which is either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn it_retries_until_the_body_arrives() {
+        // a fixture server that's unavailable, then drops the connection partway through the
+        // body, then answers properly
+        let listener = TcpListener::bind("127.0.0.1:0").expect("expected to bind");
+        let url = Url::parse(&format!("http://{}/a.png", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let responses = [
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\nhalf",
+                "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123456789",
+            ];
+            for response in responses {
+                let (mut stream, _) = listener.accept().expect("expected a request");
+                // read the whole request, so that closing doesn't reset the connection
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                stream
+                    .write_all(response.as_bytes())
+                    .expect("expected to respond");
+            }
+        });
+        let http_client = HttpClient::new(HttpOptions {
+            backoff: Duration::from_millis(1),
+            rate_limit: None,
+            ..Default::default()
+        })
+        .unwrap();
+        let response = http_client
+            .get(&url, HeaderMap::new())
+            .expect("expected the third attempt to succeed");
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, b"0123456789");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn it_reads_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        // dates in the past mean right away
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient(StatusCode::BAD_GATEWAY));
+        assert!(!is_transient(StatusCode::NOT_FOUND));
+    }
+}
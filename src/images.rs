@@ -1,156 +1,700 @@
-use reqwest::blocking::{Client, ClientBuilder};
-use ril::{Image, ImageFormat, Rgba};
+//! Loading images from files, URLs, `data:` URLs and registered sources through the
+//! [`ImageLoader`], which caches everything it downloads.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::{io::Reader as ImageReader, ImageFormat};
+use log::{debug, info, warn};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+#[cfg(feature = "network")]
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    },
+    StatusCode,
+};
+use ril::{Image, Rgba};
+use sha2::{Digest, Sha256};
 use std::{
-    env,
-    ffi::OsString,
-    fs::DirBuilder,
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    io::Cursor,
     path::{Path, PathBuf},
+    sync::Mutex,
 };
 use url::Url;
 
-use crate::error::AppError;
+use crate::{
+    cache::{check_not_current_dir, Cache, CacheEntry, CacheOptions, Validators},
+    error::Error,
+    sources::{AtlasSource, ImageSource, PlaceholderSource, S3Source},
+};
+#[cfg(feature = "network")]
+use crate::{
+    http::{HttpClient, HttpOptions},
+    sources::WikiSource,
+    wiki::{WikiResolver, DEFAULT_WIKI_API_URL},
+};
+#[cfg(feature = "network")]
+use std::sync::Arc;
+
+pub const DEFAULT_S3_ENDPOINT: &str = "https://s3.amazonaws.com";
 
+/// How an [`ImageLoader`] finds, downloads and caches images
 pub struct ImageLoaderOptions {
     pub cache_dir: PathBuf,
+    /// Relative paths are resolved against this, usually the directory of the board file
+    pub base_dir: PathBuf,
+    /// How many images `load_all` loads at once, to go easy on image hosts
+    pub max_concurrent_fetches: usize,
+    /// Only serve images from the cache, never from the network
+    pub offline: bool,
+    pub cache: CacheOptions,
+    /// The MediaWiki API that `wiki:` items are looked up with
+    #[cfg(feature = "network")]
+    pub wiki_api_url: Url,
+    /// Where `s3:` buckets live
+    pub s3_endpoint: Url,
+    #[cfg(feature = "network")]
+    pub http: HttpOptions,
 }
 
 impl Default for ImageLoaderOptions {
     fn default() -> Self {
         Self {
             cache_dir: PathBuf::from(".cache/images"),
+            base_dir: PathBuf::new(),
+            max_concurrent_fetches: 4,
+            offline: false,
+            cache: CacheOptions::default(),
+            // SAFETY: the defaults are valid URLs
+            #[cfg(feature = "network")]
+            wiki_api_url: Url::parse(DEFAULT_WIKI_API_URL).unwrap(),
+            s3_endpoint: Url::parse(DEFAULT_S3_ENDPOINT).unwrap(),
+            #[cfg(feature = "network")]
+            http: HttpOptions::default(),
         }
     }
 }
 
-/// Loads images from various sources and caches to the filesystem as necessary.
+/// Loads images from various sources and caches to the filesystem as necessary. Without the
+/// `network` feature nothing is downloaded, web URLs are only served from the cache.
 pub struct ImageLoader {
     options: ImageLoaderOptions,
-    // TODO: move this out of `new`
-    http_client: Client,
+    #[cfg(feature = "network")]
+    http_client: Arc<HttpClient>,
+    fetch_pool: ThreadPool,
+    cache: Cache,
+    /// Keyed by scheme
+    sources: HashMap<String, Box<dyn ImageSource>>,
+    stats: Mutex<LoadStats>,
+}
+
+/// What the loader has done so far, for the summary at the end of a run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadStats {
+    /// Web images served from the cache without asking the server
+    pub cache_hits: u64,
+    /// Web images that weren't cached and had to be downloaded
+    pub cache_misses: u64,
+    /// Stale cached images that were checked with the server
+    pub revalidated: u64,
+    pub bytes_downloaded: u64,
+}
+
+/// What happened when a cached image was checked against the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    /// The server says the cached copy is still current
+    NotModified,
+    /// A new copy was downloaded
+    Updated,
+}
+
+/// Where an image location points to, see [`ImageLoader::resolve`]
+enum Source<'a> {
+    Web(Url),
+    File(PathBuf),
+    Data(Url),
+    /// A registered source, along with the location minus its scheme
+    Custom(&'a dyn ImageSource, &'a str),
+}
+
+/// A response to a (possibly conditional) request for an image
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+enum Fetched {
+    NotModified,
+    Body {
+        bytes: Vec<u8>,
+        /// As claimed by the server, which isn't always right
+        content_type: Option<String>,
+        validators: Validators,
+    },
 }
 
 impl ImageLoader {
-    pub fn new(options: ImageLoaderOptions) -> Result<Self, AppError> {
-        let pwd = env::current_dir().map_err(AppError::FilesystemError)?;
-        if options.cache_dir == pwd {
-            return Err(AppError::InvalidConfigError(
-                "Image loader may not cache to the current directory.",
-            ));
-        }
+    /// Opens the cache in `options.cache_dir`, creating it if needed. Fails if the cache
+    /// would be the current directory or one of its parents.
+    pub fn new(options: ImageLoaderOptions) -> Result<Self, Error> {
+        check_not_current_dir(&options.cache_dir)?;
         // ensure cache directory exists
-        let cache_path = options.cache_dir.as_path();
-        DirBuilder::new()
-            .recursive(true)
-            .create(cache_path)
-            .map_err(AppError::FilesystemError)?;
-        // http client
-        let http_client = ClientBuilder::new()
-            .user_agent(concat!(
-                env!("CARGO_PKG_NAME"),
-                "/",
-                env!("CARGO_PKG_VERSION")
-            ))
+        let cache = Cache::open(&options.cache_dir, options.cache.clone())?;
+        #[cfg(feature = "network")]
+        let http_client = Arc::new(HttpClient::new(options.http.clone())?);
+        let fetch_pool = ThreadPoolBuilder::new()
+            .num_threads(options.max_concurrent_fetches.max(1))
+            .thread_name(|i| format!("image-fetch-{}", i))
             .build()
-            .map_err(AppError::HttpError)?;
-        Ok(Self {
+            .map_err(|e| {
+                Error::InvalidConfig(format!("couldn't start the image fetch threads: {}", e))
+            })?;
+        #[cfg(feature = "network")]
+        let item_resolver = WikiResolver::new(http_client.clone(), options.wiki_api_url.clone());
+        let s3_endpoint = options.s3_endpoint.clone();
+        let mut image_loader = Self {
             options,
+            #[cfg(feature = "network")]
             http_client,
+            fetch_pool,
+            cache,
+            sources: HashMap::new(),
+            stats: Mutex::default(),
+        };
+        // without the network, `wiki:` needs a source registered with a resolver of its own
+        #[cfg(feature = "network")]
+        image_loader.register_source(Box::new(WikiSource::new(Box::new(item_resolver))));
+        image_loader.register_source(Box::new(S3Source::new(s3_endpoint)));
+        image_loader.register_source(Box::new(AtlasSource));
+        image_loader.register_source(Box::new(PlaceholderSource));
+        Ok(image_loader)
+    }
+
+    /// Hands every location starting with `<scheme>:` to `source`, replacing whichever source
+    /// handled that scheme before.
+    pub fn register_source(&mut self, source: Box<dyn ImageSource>) {
+        self.sources.insert(source.scheme().to_string(), source);
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        self.options.cache_dir.as_path()
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.options.offline
+    }
+
+    pub fn stats(&self) -> LoadStats {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn count(&self, f: impl FnOnce(&mut LoadStats)) {
+        f(&mut self.stats.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// Checks a cached URL with the server right away, regardless of its age, downloading it
+    /// again if it has changed or isn't cached at all.
+    pub fn refresh<U: AsRef<str>>(&self, url: U) -> Result<RefreshOutcome, Error> {
+        if self.options.offline {
+            return Err(Error::InvalidConfig(
+                "cached images can't be refreshed in offline mode".to_string(),
+            ));
+        }
+        let (url, partial_cache_path) = parse_web_url_and_cache_path(url)?;
+        let key = cache_key(&partial_cache_path);
+        let entry = self.cache.lookup(&key, url.as_str())?;
+        match self.fetch(&url, entry.as_ref())? {
+            Fetched::NotModified => {
+                self.cache.revalidated(&key);
+                Ok(RefreshOutcome::NotModified)
+            }
+            Fetched::Body {
+                bytes,
+                content_type,
+                validators,
+            } => {
+                self.store(&key, &url, &bytes, content_type, validators)?;
+                Ok(RefreshOutcome::Updated)
+            }
+        }
+    }
+
+    /// Makes a best effort to load from any given source: http(s), `file:` and `data:` URLs,
+    /// registered sources like `wiki:<item name>`, and falls back to a path relative to
+    /// `base_dir` otherwise.
+    pub fn load<S: AsRef<str>>(&self, location: S) -> Result<Image<Rgba>, Error> {
+        match self.resolve(location.as_ref())? {
+            Source::Web(url) => self.load_from_url(url),
+            Source::Custom(source, reference) => source.load(reference, self),
+            Source::File(path) => self.load_from_file(path),
+            Source::Data(url) => {
+                let (media_type, bytes) = decode_data_url(&url)?;
+                ril_image_from_bytes(bytes, media_type.as_deref())
+            }
+        }
+    }
+
+    /// Works out what kind of location this is, without touching the network or filesystem.
+    fn resolve<'a>(&'a self, location: &'a str) -> Result<Source<'a>, Error> {
+        if let Some((scheme, reference)) = location.split_once(':') {
+            if let Some(source) = self.sources.get(scheme) {
+                return Ok(Source::Custom(source.as_ref(), reference));
+            }
+        }
+        // anything that doesn't parse as a URL is a path, as are single letter schemes since
+        // those are Windows drive letters
+        let url = match Url::parse(location) {
+            Ok(url) if url.scheme().len() > 1 => url,
+            _ => return Ok(Source::File(self.options.base_dir.join(location))),
+        };
+        match url.scheme() {
+            // board images are always fetched over https, but URLs that sources build, like
+            // an S3 endpoint's, go to `load_from_url` as they are
+            "http" | "https" => {
+                let mut url = url;
+                url.set_scheme("https").unwrap();
+                Ok(Source::Web(url))
+            }
+            "file" => url
+                .to_file_path()
+                .map(Source::File)
+                .map_err(|_| Error::invalid_location(location, "must be an absolute file URL")),
+            "data" => Ok(Source::Data(url)),
+            _ => Err(Error::invalid_location(
+                location,
+                "must be an http, https, file or data URL",
+            )),
+        }
+    }
+
+    /// Loads several images at once, at most `max_concurrent_fetches` at a time. Locations that
+    /// appear more than once are only loaded once. The images are returned in the same order
+    /// as `locations`, and the first error encountered is returned if any fail.
+    pub fn load_all<S: AsRef<str> + Sync>(
+        &self,
+        locations: &[S],
+    ) -> Result<Vec<Image<Rgba>>, Error> {
+        self.require_cached(locations)?;
+        let mut unique = locations.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        unique.sort_unstable();
+        unique.dedup();
+        let loaded = self.fetch_pool.install(|| {
+            unique
+                .par_iter()
+                .map(|&location| {
+                    let image = self.load(location).map_err(|source| Error::Load {
+                        location: display_location(location).into_owned(),
+                        source: Box::new(source),
+                    })?;
+                    Ok((location, image))
+                })
+                .collect::<Result<HashMap<_, _>, Error>>()
+        });
+        // cache hits only update the manifest in memory, so it's written once per batch
+        self.cache.flush()?;
+        let loaded = loaded?;
+        Ok(locations
+            .iter()
+            .map(|location| loaded[location.as_ref()].clone())
+            .collect())
+    }
+
+    /// Downloads every location that isn't cached yet, so that later loads don't need the
+    /// network. Unlike `load_all` this keeps going after a failure, and returns every location
+    /// that couldn't be fetched along with why.
+    pub fn prefetch<S: AsRef<str> + Sync>(&self, locations: &[S]) -> Vec<(String, Error)> {
+        let mut unique = locations.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        unique.sort_unstable();
+        unique.dedup();
+        self.fetch_pool.install(|| {
+            unique
+                .par_iter()
+                .filter_map(|&location| {
+                    let error = self.load(location).err()?;
+                    Some((location.to_string(), error))
+                })
+                .collect()
         })
     }
 
-    /// Makes a best effort to load from any given source, it will attempt to interpret as
-    /// a URL first, and then fall back to a path otherwise.
-    pub fn load<S: AsRef<str>>(&self, location: S) -> Result<Image<Rgba>, AppError> {
-        if let Ok(url) = Url::parse(location.as_ref()) {
-            if url.scheme() == "http" || url.scheme() == "https" {
-                return self.load_from_url(location);
+    /// Fails with every URL in `locations` that isn't cached when offline, so that they can
+    /// all be reported at once rather than one per run. Does nothing when online.
+    pub fn require_cached<S: AsRef<str>>(&self, locations: &[S]) -> Result<(), Error> {
+        if !self.options.offline {
+            return Ok(());
+        }
+        let mut missing = Vec::new();
+        for location in locations.iter().map(AsRef::as_ref) {
+            if self.needs_network(location) && !missing.iter().any(|m| m == location) {
+                missing.push(location.to_string());
             }
         }
-        let path = Path::new(location.as_ref());
-        return self.load_from_file(path);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::NotCached { locations: missing })
+        }
+    }
+
+    /// Whether loading `location` would have to download or look anything up.
+    pub fn needs_network(&self, location: &str) -> bool {
+        match self.resolve(location) {
+            Ok(Source::Web(url)) => self.url_needs_network(url.as_str()),
+            Ok(Source::Custom(source, reference)) => source.needs_network(reference, self),
+            _ => false,
+        }
     }
 
-    pub fn load_from_url<U: AsRef<str>>(&self, url: U) -> Result<Image<Rgba>, AppError> {
+    /// Whether the image at `url` would have to be downloaded. Sources use this for the URLs
+    /// they build, which unlike board locations keep their scheme.
+    pub fn url_needs_network(&self, url: &str) -> bool {
+        matches!(self.cached_path(url), Ok(None))
+    }
+
+    /// Where the image downloaded from `url` is cached, if it is.
+    fn cached_path(&self, url: &str) -> Result<Option<PathBuf>, Error> {
+        let (_, partial_cache_path) = parse_web_url_and_cache_path(url)?;
+        let cache_path = self.options.cache_dir.join(partial_cache_path);
+        Ok(cache_path.is_file().then_some(cache_path))
+    }
+
+    /// Checks that a location could be loaded without touching the network: local files must
+    /// exist, data URLs must decode and web URLs must be well-formed, and cached too when
+    /// offline.
+    pub fn check<S: AsRef<str>>(&self, location: S) -> Result<(), Error> {
+        match self.resolve(location.as_ref())? {
+            Source::Web(url) => {
+                parse_web_url_and_cache_path(url)?;
+                self.require_cached(&[location])
+            }
+            Source::Custom(source, reference) => {
+                source.check(reference, self)?;
+                self.require_cached(&[location])
+            }
+            Source::File(path) => {
+                let metadata = fs::metadata(&path).map_err(Error::io(&path))?;
+                if !metadata.is_file() {
+                    return Err(Error::invalid_location(
+                        location.as_ref(),
+                        format!("{} isn't a file", path.display()),
+                    ));
+                }
+                Ok(())
+            }
+            Source::Data(url) => decode_data_url(&url).map(|_| ()),
+        }
+    }
+
+    /// Reads the dimensions of an image without touching the network; returns `None` for
+    /// URLs that haven't been cached yet.
+    pub fn cached_dimensions<S: AsRef<str>>(
+        &self,
+        location: S,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        match self.resolve(location.as_ref())? {
+            Source::Web(url) => self.cached_url_dimensions(url.as_str()),
+            Source::Custom(source, reference) => source.cached_dimensions(reference, self),
+            Source::File(path) => file_dimensions(&path).map(Some),
+            Source::Data(url) => {
+                let (_, bytes) = decode_data_url(&url)?;
+                ImageReader::new(Cursor::new(bytes))
+                    .with_guessed_format()
+                    .map_err(|e| Error::Decode { source: e.into() })?
+                    .into_dimensions()
+                    .map(Some)
+                    .map_err(|source| Error::Decode { source })
+            }
+        }
+    }
+
+    /// Reads the dimensions of the image cached for `url`, taking the URL as it is like
+    /// `url_needs_network`; returns `None` if it hasn't been cached yet.
+    pub fn cached_url_dimensions(&self, url: &str) -> Result<Option<(u32, u32)>, Error> {
+        match self.cached_path(url)? {
+            Some(path) => file_dimensions(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_from_url<U: AsRef<str>>(&self, url: U) -> Result<Image<Rgba>, Error> {
         let (url, partial_cache_path) = parse_web_url_and_cache_path(url)?;
-        let mut cache_path = self.options.cache_dir.clone();
-        cache_path.push(partial_cache_path);
-        let cache_path = cache_path.as_path();
-        let encoding = get_encoding_from_extension(cache_path);
-        // the easy path - file exists on disk, just return it
-        if cache_path.is_file() {
-            println!("returning image from filesystem cache");
-            return self.load_from_file(cache_path);
+        let key = cache_key(&partial_cache_path);
+        // the easy path - file exists on disk and is fresh enough, just return it
+        if let Some(entry) = self.cache.lookup(&key, url.as_str())? {
+            // there's no revalidating without the network either
+            if self.options.offline || !cfg!(feature = "network") || !self.cache.is_stale(&entry) {
+                debug!(url = url.as_str(); "using the cached image");
+                self.count(|stats| stats.cache_hits += 1);
+                self.cache.touch(&key);
+                return self.load_cached(&key, &entry);
+            }
+            debug!(url = url.as_str(); "revalidating the cached image");
+            self.count(|stats| stats.revalidated += 1);
+            match self.fetch(&url, Some(&entry)) {
+                Ok(Fetched::NotModified) => {
+                    self.cache.revalidated(&key);
+                    return self.load_cached(&key, &entry);
+                }
+                Ok(Fetched::Body {
+                    bytes,
+                    content_type,
+                    validators,
+                }) => {
+                    return self.store(&key, &url, &bytes, content_type, validators);
+                }
+                Err(error) => {
+                    // a stale image beats no image
+                    warn!(
+                        url = url.as_str(), error:% = error.display_chain();
+                        "couldn't revalidate, using the cached copy"
+                    );
+                    self.cache.touch(&key);
+                    return self.load_cached(&key, &entry);
+                }
+            }
         }
         // otherwise, we need to load it
-        println!("loading image from URL: {}", url);
-        DirBuilder::new()
-            .recursive(true)
-            .create(cache_path.parent().expect("bad cache path"))
-            .map_err(AppError::FilesystemError)?;
-        let bytes = self
-            .http_client
-            .get(url)
-            .send()
-            .and_then(|r| r.bytes())
-            .map_err(AppError::HttpError)?;
-        let ril_image = ril_image_from_bytes(&bytes)?;
-        // cache it for next time
-        ril_image
-            .save(encoding, cache_path)
-            .map_err(AppError::RILError)?;
-        println!("cached imaged to filesystem: {}", cache_path.display());
+        if self.options.offline {
+            return Err(Error::NotCached {
+                locations: vec![url.to_string()],
+            });
+        }
+        info!(url = url.as_str(); "downloading image");
+        self.count(|stats| stats.cache_misses += 1);
+        match self.fetch(&url, None)? {
+            Fetched::Body {
+                bytes,
+                content_type,
+                validators,
+            } => self.store(&key, &url, &bytes, content_type, validators),
+            // can't happen without validators, but just in case
+            Fetched::NotModified => self.load_from_file(self.cache.path(&key)),
+        }
+    }
+
+    /// Requests `url`, conditionally if `entry` has validators to send along.
+    #[cfg(feature = "network")]
+    fn fetch(&self, url: &Url, entry: Option<&CacheEntry>) -> Result<Fetched, Error> {
+        let mut headers = HeaderMap::new();
+        let header = |value: &String| HeaderValue::from_str(value).ok();
+        if let Some(etag) = entry.and_then(|e| e.etag.as_ref()).and_then(header) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = entry
+            .and_then(|e| e.last_modified.as_ref())
+            .and_then(header)
+        {
+            headers.insert(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = self.http_client.get(url, headers)?;
+        if entry.is_some() && response.status == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        let validators = validators(&response.headers);
+        let content_type = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or(value).trim().to_string());
+        let bytes = response.body;
+        self.count(|stats| stats.bytes_downloaded += bytes.len() as u64);
+        Ok(Fetched::Body {
+            bytes,
+            content_type,
+            validators,
+        })
+    }
+
+    #[cfg(not(feature = "network"))]
+    fn fetch(&self, url: &Url, _entry: Option<&CacheEntry>) -> Result<Fetched, Error> {
+        Err(Error::NetworkDisabled {
+            url: url.to_string(),
+        })
+    }
+
+    /// Decodes a downloaded image and caches the bytes exactly as they were sent, for next
+    /// time. Nothing is cached if the bytes aren't an image.
+    fn store(
+        &self,
+        key: &str,
+        url: &Url,
+        bytes: &[u8],
+        content_type: Option<String>,
+        validators: Validators,
+    ) -> Result<Image<Rgba>, Error> {
+        // the bytes are a better authority on the format than the server
+        let content_type = image::guess_format(bytes)
+            .ok()
+            .map(|format| mime_type(format).to_string())
+            .or(content_type);
+        let ril_image = ril_image_from_bytes(bytes, content_type.as_deref())?;
+        self.cache
+            .store(key, url.as_str(), bytes, content_type, validators)?;
+        debug!(url = url.as_str(), path:% = self.cache.path(key).display(); "cached image");
         Ok(ril_image)
     }
 
-    pub fn load_from_file<P: AsRef<Path>>(&self, path: P) -> Result<Image<Rgba>, AppError> {
-        Image::open(path).map_err(AppError::RILError)
+    /// Decodes a cached image, using the content type it was stored with.
+    fn load_cached(&self, key: &str, entry: &CacheEntry) -> Result<Image<Rgba>, Error> {
+        let path = self.cache.path(key);
+        let bytes = fs::read(&path).map_err(Error::io(&path))?;
+        ril_image_from_bytes(bytes, entry.content_type.as_deref())
+    }
+
+    /// Loads any image format the `image` crate supports, going by the file's contents rather
+    /// than its extension.
+    pub fn load_from_file<P: AsRef<Path>>(&self, path: P) -> Result<Image<Rgba>, Error> {
+        let bytes = fs::read(path.as_ref()).map_err(Error::io(path.as_ref()))?;
+        ril_image_from_bytes(bytes, None)
     }
 }
 
-fn parse_web_url_and_cache_path<U: AsRef<str>>(url: U) -> Result<(Url, PathBuf), AppError> {
-    let mut url = Url::parse(url.as_ref()).map_err(AppError::UrlParseError)?;
-    if url.scheme() == "http" {
-        url.set_scheme("https").unwrap();
+/// Shortens `data:` URLs to their media type and size, since the whole thing is unreadable in
+/// messages. Other locations are returned as they are.
+pub fn display_location(location: &str) -> Cow<'_, str> {
+    match location.split_once(',') {
+        Some((header, data)) if header.starts_with("data:") => {
+            Cow::Owned(format!("{},... ({} characters)", header, data.len()))
+        }
+        _ => Cow::Borrowed(location),
     }
-    if url.scheme() != "https" {
-        return Err(AppError::InvalidArgumentError(
-            "url",
+}
+
+/// Decodes a base64 `data:` URL into its media type, if it has one, and bytes.
+fn decode_data_url(url: &Url) -> Result<(Option<String>, Vec<u8>), Error> {
+    // everything after "data:" is the path, since data URLs can't be a base
+    let invalid = |reason| Error::invalid_location(&display_location(url.as_str()), reason);
+    let (header, data) = url
+        .path()
+        .split_once(',')
+        .ok_or_else(|| invalid("data URL is missing the ',' before its data"))?;
+    let Some(media_type) = header.strip_suffix(";base64") else {
+        return Err(invalid("data URL must be base64 encoded"));
+    };
+    // drop parameters like ";charset=..." and tolerate the data being split over several lines
+    let media_type = media_type.split(';').next().unwrap_or_default();
+    let data = data
+        .replace("%20", "")
+        .replace(|c: char| c.is_ascii_whitespace(), "");
+    let bytes = BASE64
+        .decode(data)
+        .map_err(|_| invalid("data URL isn't valid base64"))?;
+    let media_type = (!media_type.is_empty()).then(|| media_type.to_string());
+    Ok((media_type, bytes))
+}
+
+/// Reads an image file's dimensions without decoding it, sniffing the format rather than
+/// trusting the extension, which cached files may not have.
+fn file_dimensions(path: &Path) -> Result<(u32, u32), Error> {
+    ImageReader::open(path)
+        .map_err(Error::io(path))?
+        .with_guessed_format()
+        .map_err(Error::io(path))?
+        .into_dimensions()
+        .map_err(|source| Error::Decode { source })
+}
+
+fn parse_web_url_and_cache_path<U: AsRef<str>>(url: U) -> Result<(Url, PathBuf), Error> {
+    let url = url.as_ref();
+    let url =
+        Url::parse(url).map_err(|e| Error::invalid_location(url, format!("invalid URL: {}", e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::invalid_location(
+            url.as_str(),
             "must be an http or https URL",
         ));
     }
     let host = match url.host_str() {
         Some(host) => host,
         None => {
-            return Err(AppError::InvalidArgumentError(
-                "url",
+            return Err(Error::invalid_location(
+                url.as_str(),
                 "must specify a hostname",
             ));
         }
     };
-    let path = Path::new(url.path());
-    if url.path() == "/" || path.extension().is_none() {
-        return Err(AppError::InvalidArgumentError(
-            "url",
-            "must specify a path to a file",
-        ));
-    }
 
+    // the path is only there to make the cache browsable, the hash of the whole URL (query
+    // string included) at the end of the file name is what keeps entries apart
     let mut cache_path = PathBuf::new();
-    for fragment in host.split(".") {
-        cache_path.push(fragment);
+    for fragment in host.split('.') {
+        cache_path.push(sanitize_path_fragment(fragment));
     }
     // SAFETY: unwrap is safe here because the check for host above fails on data:, mailto:, and file:
-    // and the check for an empty path above guarantees this iterator is non-empty
-    for fragment in url.path_segments().unwrap() {
+    let mut fragments = url
+        .path_segments()
+        .unwrap()
+        .filter(|f| !f.is_empty())
+        .map(sanitize_path_fragment)
+        .collect::<Vec<_>>();
+    let file_name = fragments.pop().unwrap_or_else(|| "index".to_string());
+    for fragment in fragments {
         cache_path.push(fragment);
     }
+    let hash = format!("{:x}", Sha256::digest(url.as_str().as_bytes()));
+    cache_path.push(format!("{}-{}", file_name, &hash[..16]));
 
     Ok((url, cache_path))
 }
 
-fn ril_image_from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Image<Rgba>, AppError> {
-    let loaded = image::load_from_memory(bytes.as_ref())
-        .map_err(AppError::ImageError)?
+/// Keeps the characters that are safe in a file name on every platform, and caps the length.
+fn sanitize_path_fragment(fragment: &str) -> String {
+    fragment
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .take(64)
+        .collect::<String>()
+        // "." and ".." aren't names
+        .replace("..", "__")
+}
+
+/// The manifest key of a cached file, its path relative to the cache directory.
+fn cache_key(partial_cache_path: &Path) -> String {
+    partial_cache_path
+        .iter()
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(feature = "network")]
+fn validators(headers: &HeaderMap) -> Validators {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    }
+}
+
+/// Decodes an image, in the format given by `content_type` if it's one that's recognized, and
+/// sniffed from the bytes otherwise.
+fn ril_image_from_bytes<B: AsRef<[u8]>>(
+    bytes: B,
+    content_type: Option<&str>,
+) -> Result<Image<Rgba>, Error> {
+    let mut reader = ImageReader::new(Cursor::new(bytes.as_ref()));
+    match content_type.and_then(ImageFormat::from_mime_type) {
+        Some(format) => reader.set_format(format),
+        None => {
+            reader = reader
+                .with_guessed_format()
+                .map_err(|e| Error::Decode { source: e.into() })?
+        }
+    }
+    let loaded = reader
+        .decode()
+        .map_err(|source| Error::Decode { source })?
         .to_rgba8();
     Ok(Image::from_fn(loaded.width(), loaded.height(), |x, y| {
         let p = loaded.get_pixel(x, y);
@@ -163,22 +707,56 @@ fn ril_image_from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Image<Rgba>, AppErro
     }))
 }
 
-fn get_encoding_from_extension<P: AsRef<Path>>(path: P) -> ImageFormat {
-    let extension = path
-        .as_ref()
-        .extension()
-        .map(|e| e.to_owned().to_ascii_lowercase())
-        .unwrap_or(OsString::new());
-    match extension.to_str() {
-        Some("jpg") | Some("jpeg") => ImageFormat::Jpeg,
-        Some("png") => ImageFormat::Png,
-        _ => ImageFormat::Unknown,
+/// The MIME type of an image format; `image` doesn't have this built in until a later version.
+fn mime_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Pnm => "image/x-portable-anymap",
+        ImageFormat::Tiff => "image/tiff",
+        ImageFormat::Tga => "image/x-targa",
+        ImageFormat::Dds => "image/vnd-ms.dds",
+        ImageFormat::Bmp => "image/bmp",
+        ImageFormat::Ico => "image/x-icon",
+        ImageFormat::Hdr => "image/vnd.radiance",
+        ImageFormat::OpenExr => "image/x-exr",
+        ImageFormat::Avif => "image/avif",
+        _ => "application/octet-stream",
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
     use super::*;
+    use crate::test_util::TempDir;
+
+    /// A 2x1 PNG, base64 encoded
+    const PIXEL: &str = "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAADklEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII=";
+
+    /// Makes `size:<width>x<height>` images, keeping track of every reference it's asked for.
+    struct SizeSource {
+        loaded: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ImageSource for SizeSource {
+        fn scheme(&self) -> &str {
+            "size"
+        }
+
+        fn load(&self, reference: &str, _loader: &ImageLoader) -> Result<Image<Rgba>, Error> {
+            self.loaded.lock().unwrap().push(reference.to_string());
+            let size = reference
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+            let (width, height) =
+                size.ok_or_else(|| Error::invalid_location(reference, "expected a size"))?;
+            Ok(Image::new(width, height, Rgba::transparent()))
+        }
+    }
 
     #[test]
     fn it_parses() {
@@ -186,17 +764,187 @@ mod tests {
             "https://oldschool.runescape.wiki/images/Serpentine_helm_detail.png",
         )
         .expect("expected parse to succeed");
-        let actual = cache_path.as_path();
-        let expected = {
-            let mut buf = PathBuf::new();
-            buf.push("oldschool");
-            buf.push("runescape");
-            buf.push("wiki");
-            buf.push("images");
-            buf.push("Serpentine_helm_detail.png");
-            buf
+        let mut parts = cache_path.iter().map(|p| p.to_str().unwrap());
+        assert_eq!(parts.next(), Some("oldschool"));
+        assert_eq!(parts.next(), Some("runescape"));
+        assert_eq!(parts.next(), Some("wiki"));
+        assert_eq!(parts.next(), Some("images"));
+        let file_name = parts.next().expect("expected a file name");
+        assert!(file_name.starts_with("Serpentine_helm_detail.png-"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn it_keys_on_the_whole_url() {
+        let key = |url| {
+            let (_, cache_path) = parse_web_url_and_cache_path(url).expect("expected a key");
+            cache_key(&cache_path)
         };
-        let expected = expected.as_path();
-        assert_eq!(expected, actual);
+        assert_ne!(
+            key("https://example.com/helm.png?version=2"),
+            key("https://example.com/helm.png?version=3")
+        );
+        // URLs keep their scheme, it's board locations that are upgraded to https
+        assert_ne!(
+            key("http://example.com/helm.png"),
+            key("https://example.com/helm.png")
+        );
+        assert!(key("https://i.imgur.com/abc").starts_with("i/imgur/com/abc-"));
+        assert!(key("https://example.com/").starts_with("example/com/index-"));
+        let dir = TempDir::new("keys");
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            image_loader.resolve("http://example.com/helm.png"),
+            Ok(Source::Web(url)) if url.scheme() == "https"
+        ));
+    }
+
+    #[test]
+    fn it_decodes_data_urls() {
+        // a 2x1 PNG, split over two lines the way a folded YAML string would be
+        let url = Url::parse(
+            "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAAD \
+             klEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII=",
+        )
+        .expect("expected a URL");
+        let (media_type, bytes) = decode_data_url(&url).expect("expected data URL to decode");
+        assert_eq!(media_type.as_deref(), Some("image/png"));
+        let image = ril_image_from_bytes(bytes, media_type.as_deref()).expect("expected a PNG");
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(
+            display_location(url.as_str()),
+            "data:image/png;base64,... (97 characters)"
+        );
+        let url = Url::parse("data:text/plain,hello").unwrap();
+        assert!(decode_data_url(&url).is_err());
+    }
+
+    #[test]
+    fn it_loads_each_location_once_in_order() {
+        let dir = TempDir::new("load-all");
+        let mut image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap();
+        let loaded = Arc::new(Mutex::new(Vec::new()));
+        image_loader.register_source(Box::new(SizeSource {
+            loaded: loaded.clone(),
+        }));
+        let locations = ["size:3x1", "size:1x2", "size:3x1", "size:2x2", "size:1x2"];
+        let images = image_loader.load_all(&locations).unwrap();
+        let dimensions = images.iter().map(Image::dimensions).collect::<Vec<_>>();
+        assert_eq!(dimensions, [(3, 1), (1, 2), (3, 1), (2, 2), (1, 2)]);
+        let mut loaded = loaded.lock().unwrap().clone();
+        loaded.sort();
+        assert_eq!(loaded, ["1x2", "2x2", "3x1"]);
+        // one bad location fails the lot
+        let result = image_loader.load_all(&["size:1x1", "size:huge"]);
+        assert!(matches!(result, Err(Error::Load { location, .. }) if location == "size:huge"));
+    }
+
+    #[test]
+    fn it_lists_every_uncached_image_when_offline() {
+        let dir = TempDir::new("offline");
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.path().to_path_buf(),
+            offline: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let cached = "https://example.com/cached.png";
+        let (_, partial_cache_path) = parse_web_url_and_cache_path(cached).unwrap();
+        let pixel = BASE64.decode(PIXEL).unwrap();
+        image_loader
+            .cache
+            .store(
+                &cache_key(&partial_cache_path),
+                cached,
+                &pixel,
+                Some("image/png".to_string()),
+                Validators::default(),
+            )
+            .unwrap();
+        let data_url = format!("data:image/png;base64,{PIXEL}");
+        let locations = [
+            cached,
+            "https://example.com/a.png",
+            &data_url,
+            "https://example.com/b.png",
+            "https://example.com/a.png",
+        ];
+        // every missing URL is reported once, in order, before anything is loaded
+        let expected = ["https://example.com/a.png", "https://example.com/b.png"];
+        assert!(matches!(
+            image_loader.require_cached(&locations),
+            Err(Error::NotCached { locations }) if locations == expected
+        ));
+        assert!(matches!(
+            image_loader.load_all(&locations),
+            Err(Error::NotCached { locations }) if locations == expected
+        ));
+        assert!(matches!(
+            image_loader.check("https://example.com/b.png"),
+            Err(Error::NotCached { .. })
+        ));
+        // what is cached still loads
+        assert!(image_loader.check(cached).is_ok());
+        let images = image_loader.load_all(&[cached, locations[2]]).unwrap();
+        assert_eq!(images[0].dimensions(), (2, 1));
+    }
+
+    #[test]
+    fn it_caches_what_the_server_sent() {
+        for format in [
+            ImageFormat::Png,
+            ImageFormat::Jpeg,
+            ImageFormat::Gif,
+            ImageFormat::WebP,
+            ImageFormat::Bmp,
+        ] {
+            assert_eq!(ImageFormat::from_mime_type(mime_type(format)), Some(format));
+        }
+        let dir = TempDir::new("raw-cache");
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut gif = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            3,
+            2,
+            image::Rgba([255, 0, 0, 255]),
+        ))
+        .write_to(&mut Cursor::new(&mut gif), ImageFormat::Gif)
+        .unwrap();
+        // an extension-less URL, and a server that gets the content type wrong
+        let url = Url::parse("https://example.com/images/helm").unwrap();
+        let (_, partial_cache_path) = parse_web_url_and_cache_path(url.as_str()).unwrap();
+        let key = cache_key(&partial_cache_path);
+        let stored = image_loader
+            .store(
+                &key,
+                &url,
+                &gif,
+                Some("image/png".to_string()),
+                Validators::default(),
+            )
+            .unwrap();
+        assert_eq!(stored.dimensions(), (3, 2));
+        assert_eq!(fs::read(image_loader.cache.path(&key)).unwrap(), gif);
+        let entry = image_loader
+            .cache
+            .lookup(&key, url.as_str())
+            .unwrap()
+            .expect("expected the image to be cached");
+        assert_eq!(entry.content_type.as_deref(), Some("image/gif"));
+        let cached = image_loader.load_cached(&key, &entry).unwrap();
+        assert_eq!(cached.dimensions(), (3, 2));
+        assert_eq!(cached.pixel(1, 1), &Rgba::new(255, 0, 0, 255));
     }
 }
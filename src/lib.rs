@@ -0,0 +1,122 @@
+//! Builds bingo board images from YAML board definitions.
+//!
+//! A board starts out as a [`BoardBuilder`], usually deserialized from YAML. Building it
+//! validates it and loads its images through an [`ImageLoader`], which gives a [`Board`] that
+//! a [`BoardRenderer`] draws, one tile at a time with a [`TileRenderer`] and a
+//! [`TextRenderer`]. [`render_board`] does all of that in one go:
+//!
+//! ```no_run
+//! use boardbuilder::{
+//!     error::Error, images::ImageLoaderOptions, render_board, BoardBuilder, ImageLoader,
+//! };
+//!
+//! let yaml = std::fs::read_to_string("board.yaml").map_err(Error::io("board.yaml"))?;
+//! let config: BoardBuilder =
+//!     serde_yaml::from_str(&yaml).map_err(|source| Error::Yaml { source })?;
+//! let image_loader = ImageLoader::new(ImageLoaderOptions::default())?;
+//! let image = render_board(config, &image_loader)?;
+//! image.save(ril::ImageFormat::Png, "board.png")?;
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! [`BoardBuilder::validate`] reports every problem with a board at once, as
+//! [`diagnostics::Diagnostic`]s. Team [`progress`] files are applied on top of a shared board
+//! and [`scoring`] scores the result. Locations with schemes of their own, like `wiki:`, come
+//! from [`sources`], and [`ImageLoader::register_source`] takes new ones.
+//!
+//! Everything fails with an [`Error`], which says what it was about (the tile, URL or file)
+//! and keeps its cause. [`Error::code`] gives a stable code to match on, like `E0405` for an
+//! image a tile needs that couldn't be loaded.
+//!
+//! # Features
+//!
+//! - `network` (on by default) downloads images, with retries and rate limiting, and looks up
+//!   `item`s on the OSRS Wiki. Without it only local files, `data:` URLs and images already in
+//!   the cache can be loaded, and `reqwest` isn't pulled in.
+//! - `cli` (on by default) builds the `boardbuilder` command line tool.
+
+use ril::{Image, Rgba};
+
+use crate::error::Error;
+
+pub mod background;
+pub mod board;
+pub mod builder;
+pub mod cache;
+pub mod captions;
+pub mod diagnostics;
+pub mod error;
+#[cfg(feature = "network")]
+pub mod http;
+pub mod images;
+pub mod progress;
+pub mod scoring;
+pub mod sources;
+pub mod text;
+pub mod tile;
+#[cfg(feature = "network")]
+pub mod wiki;
+
+mod palette;
+#[cfg(test)]
+mod test_util;
+
+pub use board::{Board, BoardRenderer};
+pub use builder::BoardBuilder;
+pub use images::ImageLoader;
+pub use text::TextRenderer;
+pub use tile::TileRenderer;
+
+/// Validates a board, loads its images and renders it with the default font.
+pub fn render_board(
+    config: BoardBuilder,
+    image_loader: &ImageLoader,
+) -> Result<Image<Rgba>, Error> {
+    let board = config.build(image_loader)?;
+    let text_renderer = TextRenderer::default();
+    let tile_renderer = TileRenderer::new(&text_renderer);
+    Ok(BoardRenderer::new(&tile_renderer).render(&board))
+}
+
+#[cfg(test)]
+mod tests {
+    use ril::ImageFormat;
+
+    use super::*;
+    use crate::{images::ImageLoaderOptions, test_util::TempDir};
+
+    #[test]
+    fn it_renders_boards_from_local_images() {
+        // nothing here needs the network, so this has to pass with --no-default-features too
+        let dir = TempDir::new("render");
+        let icon = dir.join("icon.png");
+        Image::new(8, 8, Rgba::new(255, 0, 0, 255))
+            .save(ImageFormat::Png, &icon)
+            .unwrap();
+        let yaml = format!(
+            r##"
+rows: 1
+cols: 2
+width: 300
+height: 200
+tile_size: auto
+tile_render_options: {{padding: 4, border_size: 2, inset_size: 2, text_size: 16}}
+background: {{color: "#000000FF"}}
+tiles:
+  - {{number: 1, name: File, image: "{}"}}
+  - number: 2
+    name: Data
+    image: "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAADklEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII="
+"##,
+            icon.display()
+        );
+        let config: BoardBuilder = serde_yaml::from_str(&yaml).unwrap();
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.join("cache"),
+            ..Default::default()
+        })
+        .unwrap();
+        let image = render_board(config, &image_loader).unwrap();
+        assert_eq!(image.dimensions(), (300, 200));
+    }
+}
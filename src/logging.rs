@@ -0,0 +1,381 @@
+//! Log output: leveled messages on stderr, either as text or as one JSON object per line,
+//! and the summary that's logged at the end of a run.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write as _},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{
+    kv::{self, Key, Source, Value, VisitSource},
+    Level, LevelFilter, Log, Metadata, Record,
+};
+use serde_json::{Map, Number};
+
+use boardbuilder::{
+    diagnostics::{Diagnostic, Report, Severity},
+    images::LoadStats,
+};
+
+use crate::cli::LogFormat;
+
+/// Records from other crates, like the HTTP client, are only shown at this level and above
+const DEPENDENCY_LEVEL: Level = Level::Warn;
+/// Diagnostics about a file, which are already rendered in text mode
+const DIAGNOSTIC_TARGET: &str = concat!(env!("CARGO_CRATE_NAME"), "::diagnostic");
+
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+/// Installs the logger for the rest of the run.
+pub fn init(level: LevelFilter, format: LogFormat) {
+    // only fails if a logger is already installed, in which case that one stays
+    if log::set_boxed_logger(Box::new(Logger { level, format })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+            && (metadata.level() <= DEPENDENCY_LEVEL
+                || metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = match self.format {
+            LogFormat::Text => text_line(record),
+            LogFormat::Json => json_line(record),
+        };
+        // there's nowhere left to report a failure to write to stderr
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// `<level>: <message> key=value ...`, to match how errors are printed
+fn text_line(record: &Record) -> String {
+    if record.target() == DIAGNOSTIC_TARGET {
+        return record.args().to_string();
+    }
+    let level = match record.level() {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    };
+    let mut line = format!("{}: {}", level, record.args());
+    let _ = record.key_values().visit(&mut TextPairs(&mut line));
+    line
+}
+
+struct TextPairs<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for TextPairs<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        // values with spaces are quoted so that the pairs can still be told apart; writing to
+        // a string can't fail
+        let value = value.to_string();
+        let _ = if value.contains(char::is_whitespace) {
+            write!(self.0, " {}={:?}", key, value)
+        } else {
+            write!(self.0, " {}={}", key, value)
+        };
+        Ok(())
+    }
+}
+
+/// `{"ts": <unix millis>, "level": ..., "target": ..., "message": ..., <key>: <value>, ...}`
+fn json_line(record: &Record) -> String {
+    let mut object = Map::new();
+    let _ = record.key_values().visit(&mut JsonPairs(&mut object));
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    object.insert("ts".into(), ts.into());
+    object.insert(
+        "level".into(),
+        record.level().as_str().to_lowercase().into(),
+    );
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+    serde_json::Value::Object(object).to_string()
+}
+
+struct JsonPairs<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonPairs<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        // numbers and booleans keep their type, everything else is written out as a string
+        let value = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_f64().and_then(Number::from_f64) {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Logs a problem found in a file: rustc-style against the source in text mode, or with its
+/// code, location and notes as fields in JSON mode. Errors are logged at error level, warnings
+/// at warn and notes at info, so `-q` hides everything but errors.
+pub fn diagnostic(format: LogFormat, report: &Report, diagnostic: &Diagnostic) {
+    let level = match diagnostic.severity {
+        Severity::Error => Level::Error,
+        Severity::Warning => Level::Warn,
+        Severity::Note => Level::Info,
+    };
+    let builder = |args| {
+        let mut builder = Record::builder();
+        builder.level(level).target(DIAGNOSTIC_TARGET).args(args);
+        builder
+    };
+    match format {
+        LogFormat::Text => {
+            let rendered = report.render(diagnostic);
+            log::logger().log(&builder(format_args!("{}", rendered.trim_end())).build());
+        }
+        LogFormat::Json => {
+            let source = DiagnosticSource::new(report, diagnostic);
+            let message = &diagnostic.message;
+            log::logger().log(
+                &builder(format_args!("{}", message))
+                    .key_values(&source)
+                    .build(),
+            );
+        }
+    }
+}
+
+struct DiagnosticSource<'a> {
+    report: &'a Report<'a>,
+    diagnostic: &'a Diagnostic,
+    /// Every note, separated by `; `
+    notes: String,
+}
+
+impl<'a> DiagnosticSource<'a> {
+    fn new(report: &'a Report<'a>, diagnostic: &'a Diagnostic) -> Self {
+        Self {
+            report,
+            diagnostic,
+            notes: diagnostic.notes.join("; "),
+        }
+    }
+}
+
+impl Source for DiagnosticSource<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        let diagnostic = self.diagnostic;
+        if let Some(code) = diagnostic.code {
+            visitor.visit_pair(Key::from_str("code"), Value::from(code))?;
+        }
+        visitor.visit_pair(Key::from_str("file"), Value::from(self.report.file_name()))?;
+        if let Some(span) = self.report.span(diagnostic) {
+            visitor.visit_pair(Key::from_str("line"), Value::from(span.line))?;
+            visitor.visit_pair(Key::from_str("column"), Value::from(span.column))?;
+        }
+        if let Some(path) = &diagnostic.path {
+            visitor.visit_pair(Key::from_str("path"), Value::from(path.as_str()))?;
+        }
+        if !self.notes.is_empty() {
+            visitor.visit_pair(Key::from_str("notes"), Value::from(self.notes.as_str()))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a run did, logged once at the end: the loader's cache stats and how long each phase
+/// took.
+pub struct RunSummary {
+    started_at: Instant,
+    /// Keyed `<phase>_ms`, in the order they ran
+    phases: Vec<(String, Duration)>,
+}
+
+impl Default for RunSummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunSummary {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Runs `f`, adding the time it took to `phase`.
+    pub fn time<T>(&mut self, phase: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let key = format!("{}_ms", phase);
+        match self.phases.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, duration)) => *duration += start.elapsed(),
+            None => self.phases.push((key, start.elapsed())),
+        }
+        result
+    }
+
+    pub fn log(&self, stats: LoadStats) {
+        let source = SummarySource {
+            summary: self,
+            stats,
+            total: self.started_at.elapsed(),
+        };
+        log::logger().log(
+            &Record::builder()
+                .level(Level::Info)
+                .target(concat!(env!("CARGO_CRATE_NAME"), "::summary"))
+                .args(format_args!("summary"))
+                .key_values(&source)
+                .build(),
+        );
+    }
+}
+
+struct SummarySource<'a> {
+    summary: &'a RunSummary,
+    stats: LoadStats,
+    total: Duration,
+}
+
+impl Source for SummarySource<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        let LoadStats {
+            cache_hits,
+            cache_misses,
+            revalidated,
+            bytes_downloaded,
+        } = self.stats;
+        visitor.visit_pair(Key::from_str("cache_hits"), Value::from(cache_hits))?;
+        visitor.visit_pair(Key::from_str("cache_misses"), Value::from(cache_misses))?;
+        visitor.visit_pair(Key::from_str("revalidated"), Value::from(revalidated))?;
+        visitor.visit_pair(
+            Key::from_str("bytes_downloaded"),
+            Value::from(bytes_downloaded),
+        )?;
+        for (key, duration) in self.summary.phases.iter() {
+            visitor.visit_pair(Key::from_str(key), Value::from(millis(*duration)))?;
+        }
+        visitor.visit_pair(Key::from_str("total_ms"), Value::from(millis(self.total)))
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    #[test]
+    fn it_maps_flags_to_levels() {
+        let level = |args: &[&str]| {
+            let args = ["boardbuilder"].iter().chain(args).chain(&["init"]);
+            Cli::parse_from(args).global.log_level()
+        };
+        assert_eq!(level(&[]), LevelFilter::Info);
+        assert_eq!(level(&["-v"]), LevelFilter::Debug);
+        assert_eq!(level(&["-vv"]), LevelFilter::Trace);
+        assert_eq!(level(&["-q"]), LevelFilter::Error);
+    }
+
+    #[test]
+    fn it_formats_records() {
+        let pairs = [("url", "https://example.com/a b.png")];
+        let bytes = [("bytes", 512)];
+        let record = |args, key_values: &dyn Source| {
+            let line = |format: fn(&Record) -> String| {
+                format(
+                    &Record::builder()
+                        .level(Level::Warn)
+                        .target("boardbuilder::images")
+                        .args(args)
+                        .key_values(key_values)
+                        .build(),
+                )
+            };
+            (line(text_line), line(json_line))
+        };
+        let (text, json) = record(format_args!("retrying"), &pairs);
+        assert_eq!(
+            text,
+            r#"warning: retrying url="https://example.com/a b.png""#
+        );
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["level"], "warn");
+        assert_eq!(json["message"], "retrying");
+        assert_eq!(json["url"], "https://example.com/a b.png");
+        // numbers stay numbers
+        let (text, json) = record(format_args!("downloaded"), &bytes);
+        assert_eq!(text, "warning: downloaded bytes=512");
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["bytes"], 512);
+    }
+
+    #[test]
+    fn it_formats_diagnostics_and_summaries() {
+        let report = Report::new("board.yaml", "rows: 1\ncols: 0\n");
+        let diagnostic = Diagnostic::error("cols must be at least 1").at("cols");
+        let source = DiagnosticSource::new(&report, &diagnostic);
+        let line = json_line(
+            &Record::builder()
+                .level(Level::Error)
+                .target(DIAGNOSTIC_TARGET)
+                .args(format_args!("{}", diagnostic.message))
+                .key_values(&source)
+                .build(),
+        );
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["file"], "board.yaml");
+        assert_eq!((&json["line"], &json["column"]), (&2.into(), &7.into()));
+        assert_eq!(json["path"], "cols");
+        // phases that run more than once add up
+        let mut summary = RunSummary::new();
+        summary.time("render", || ());
+        summary.time("write", || ());
+        summary.time("render", || ());
+        let source = SummarySource {
+            summary: &summary,
+            stats: LoadStats::default(),
+            total: Duration::from_millis(1500),
+        };
+        let line = text_line(
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("summary"))
+                .key_values(&source)
+                .build(),
+        );
+        assert_eq!(
+            line,
+            "info: summary cache_hits=0 cache_misses=0 revalidated=0 bytes_downloaded=0 \
+             render_ms=0 write_ms=0 total_ms=1500"
+        );
+    }
+}
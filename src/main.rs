@@ -1,43 +1,455 @@
-use std::{env, fs::File};
-
-use anyhow::Result;
-use board::BoardRenderer;
-use builder::BoardBuilder;
-use error::AppError;
-use images::ImageLoader;
-use ril::prelude::*;
-use text::TextRenderer;
-use tile::TileRenderer;
-
-mod board;
-mod builder;
-mod error;
-mod images;
-mod palette;
-mod text;
-mod tile;
-
-fn main() -> Result<()> {
-    // TODO: arg parser
-    let args = env::args();
-    assert_eq!(args.len(), 3, "wrong number of arguments");
-    let args = args.into_iter().skip(1).collect::<Vec<_>>();
-    let [input_path, output_path]: &[String; 2] = args[..].try_into().unwrap();
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
+use boardbuilder::{
+    cache::{format_age, format_size, CacheOptions},
+    diagnostics::{Diagnostic, Report, Severity},
+    error::Error,
+    http::HttpOptions,
+    images::{ImageLoaderOptions, RefreshOutcome},
+    progress::TeamProgress,
+    scoring::Score,
+    BoardBuilder, BoardRenderer, ImageLoader, TextRenderer, TileRenderer,
+};
+use clap::Parser;
+use cli::{
+    BatchArgs, CacheCommand, Cli, Command, GlobalArgs, InitArgs, LogFormat, PrefetchArgs,
+    RenderArgs, ReportFormat, ScoreArgs, ValidateArgs,
+};
+use log::{debug, error, warn};
+use logging::RunSummary;
+use serde::de::DeserializeOwned;
+
+mod cli;
+mod logging;
+
+const BOARD_TEMPLATE: &str = include_str!("../assets/templates/board.yaml");
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    logging::init(cli.global.log_level(), cli.global.log_format);
+    let result = match &cli.command {
+        Command::Render(args) => render(&cli.global, args),
+        Command::Batch(args) => batch(&cli.global, args),
+        Command::Score(args) => score(&cli.global, args),
+        Command::Validate(args) => validate(&cli.global, args),
+        Command::Prefetch(args) => prefetch(&cli.global, args),
+        Command::Cache(command) => cache(&cli.global, command),
+        Command::Init(args) => init(&cli.global, args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report_error(&cli.global, &error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints the error that ended the run, with its code and what caused it. In JSON mode it's
+/// logged instead, so that stderr stays one object per line.
+fn report_error(global: &GlobalArgs, error: &Error) {
+    if matches!(global.log_format, LogFormat::Json) {
+        error!(code = error.code(); "{}", error.display_chain());
+        return;
+    }
+    eprintln!("error[{}]: {}", error.code(), error);
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        eprintln!("  caused by: {}", cause);
+        source = cause.source();
+    }
+}
+
+fn render(global: &GlobalArgs, args: &RenderArgs) -> Result<()> {
     // deps
-    let image_loader = ImageLoader::new(Default::default())?;
+    let image_loader = image_loader(global, &args.input)?;
     let text_renderer = TextRenderer::default();
     let tile_renderer = TileRenderer::new(&text_renderer);
     let board_renderer = BoardRenderer::new(&tile_renderer);
 
     // loading
-    let board_builder: BoardBuilder = serde_yaml::from_reader(File::open(input_path)?)?;
-    let board = board_builder.build(&image_loader)?;
+    let mut summary = RunSummary::new();
+    let board_builder = summary.time("validate", || {
+        check_board(global, &args.input, &image_loader, &text_renderer)
+    })?;
+    let mut board = summary.time("load", || board_builder.build(&image_loader))?;
+    if let Some(progress_path) = &args.progress {
+        let progress = check_progress(global, progress_path, board.tiles.len())?;
+        board.apply_progress(&progress);
+    }
+
+    let board_image = summary.time("render", || board_renderer.render(&board));
+    summary.time("write", || {
+        board_image
+            .save(args.output_format(), &args.output)
+            .map_err(|e| Error::Save {
+                path: args.output.clone(),
+                reason: e.to_string(),
+            })
+    })?;
+    debug!(path:% = args.output.display(); "wrote board image");
+    summary.log(image_loader.stats());
+
+    Ok(())
+}
 
-    let board_image = board_renderer.render(&board);
-    board_image
-        .save(ImageFormat::Png, output_path)
-        .map_err(AppError::RILError)?;
+fn batch(global: &GlobalArgs, args: &BatchArgs) -> Result<()> {
+    // deps
+    let image_loader = image_loader(global, &args.input)?;
+    let text_renderer = TextRenderer::default();
+    let tile_renderer = TileRenderer::new(&text_renderer);
+    let board_renderer = BoardRenderer::new(&tile_renderer);
+
+    // parse every progress file up front so that a typo doesn't waste a full board load
+    let mut summary = RunSummary::new();
+    let (board_builder, teams) = summary.time("validate", || -> Result<_> {
+        let board_builder = check_board(global, &args.input, &image_loader, &text_renderer)?;
+        let tile_count = board_builder.tiles.len();
+        let teams = progress_files(&args.progress_dir)?
+            .into_iter()
+            .map(|path| Ok((check_progress(global, &path, tile_count)?, path)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((board_builder, teams))
+    })?;
+    if teams.is_empty() {
+        return Err(Error::NoProgressFiles {
+            dir: args.progress_dir.clone(),
+        });
+    }
+    let output_paths = batch_output_paths(args, teams.iter().map(|(_, path)| path.as_path()))?;
+
+    // images are only loaded once, then each team's progress is swapped in
+    let mut board = summary.time("load", || board_builder.build(&image_loader))?;
+    fs::create_dir_all(&args.output_dir).map_err(Error::io(&args.output_dir))?;
+    for ((progress, path), output_path) in teams.iter().zip(output_paths) {
+        board.apply_progress(progress);
+        let file_stem = path.file_stem().unwrap_or_default();
+        let board_image = summary.time("render", || board_renderer.render(&board));
+        summary.time("write", || {
+            board_image
+                .save(args.format.into(), &output_path)
+                .map_err(|e| Error::Save {
+                    path: output_path.clone(),
+                    reason: e.to_string(),
+                })
+        })?;
+        let team = progress
+            .team
+            .clone()
+            .unwrap_or_else(|| file_stem.to_string_lossy().into_owned());
+        debug!(team = team.as_str(), path:% = output_path.display(); "wrote board");
+    }
+    summary.log(image_loader.stats());
+    Ok(())
+}
+
+/// Where each progress file's board is written: its name with the extension swapped for the
+/// output format's, so `team.a.yaml` becomes `team.a.png`. Fails before anything is rendered
+/// if two progress files would overwrite each other, like `a.yaml` and `a.yml`.
+fn batch_output_paths<'a>(
+    args: &BatchArgs,
+    progress_paths: impl Iterator<Item = &'a Path>,
+) -> Result<Vec<PathBuf>> {
+    let mut seen = HashMap::new();
+    let mut output_paths = Vec::new();
+    for path in progress_paths {
+        let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(args.format.extension());
+        let output_path = args.output_dir.join(file_name);
+        if let Some(first) = seen.insert(output_path.clone(), path) {
+            return Err(Error::DuplicateOutput {
+                first: first.to_path_buf(),
+                second: path.to_path_buf(),
+                output: output_path,
+            });
+        }
+        output_paths.push(output_path);
+    }
+    Ok(output_paths)
+}
 
+fn score(global: &GlobalArgs, args: &ScoreArgs) -> Result<()> {
+    let image_loader = image_loader(global, &args.input)?;
+    let text_renderer = TextRenderer::default();
+    let board_builder = check_board(global, &args.input, &image_loader, &text_renderer)?;
+    let progress = match &args.progress {
+        Some(path) => Some(check_progress(global, path, board_builder.tiles.len())?),
+        None => None,
+    };
+    let score = Score::for_board_builder(&board_builder, progress.as_ref());
+    match args.format {
+        // SAFETY: a score is only numbers and lists of them, which always serialize
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&score).unwrap()),
+        ReportFormat::Text => {
+            println!(
+                "{}/{} tiles completed{}",
+                score.tiles_completed,
+                score.tiles_total,
+                if score.blackout { " (blackout)" } else { "" }
+            );
+            let lines = score.lines.iter().map(ToString::to_string);
+            let lines = lines.collect::<Vec<_>>().join(", ");
+            if lines.is_empty() {
+                println!("no completed lines");
+            } else {
+                println!("{} completed line(s): {}", score.lines.len(), lines);
+            }
+            println!("{} points", score.points);
+        }
+    }
     Ok(())
 }
+
+fn validate(global: &GlobalArgs, args: &ValidateArgs) -> Result<()> {
+    let image_loader = image_loader(global, &args.input)?;
+    let text_renderer = TextRenderer::default();
+    let board_builder = check_board(global, &args.input, &image_loader, &text_renderer)?;
+    if !global.quiet {
+        println!(
+            "{} is valid: {}x{} board with {} tiles",
+            args.input.display(),
+            board_builder.rows,
+            board_builder.cols,
+            board_builder.tiles.len()
+        );
+    }
+    Ok(())
+}
+
+fn prefetch(global: &GlobalArgs, args: &PrefetchArgs) -> Result<()> {
+    if global.offline {
+        return Err(Error::InvalidConfig(
+            "prefetch downloads images, so it can't be used with --offline".to_string(),
+        ));
+    }
+    let image_loader = image_loader(global, &args.input)?;
+    let text_renderer = TextRenderer::default();
+    let mut summary = RunSummary::new();
+    let board_builder = summary.time("validate", || {
+        check_board(global, &args.input, &image_loader, &text_renderer)
+    })?;
+    let mut locations = board_builder.image_locations();
+    locations.sort_unstable();
+    locations.dedup();
+    let failures = summary.time("download", || image_loader.prefetch(&locations));
+    for (location, error) in failures.iter() {
+        error!(
+            location = location.as_str(), code = error.code(), error:% = error.display_chain();
+            "failed to fetch image"
+        );
+    }
+    summary.log(image_loader.stats());
+    if !failures.is_empty() {
+        return Err(Error::PrefetchFailed {
+            failed: failures.len(),
+            total: locations.len(),
+        });
+    }
+    if !global.quiet {
+        println!(
+            "all {} images for {} are cached in {}",
+            locations.len(),
+            args.input.display(),
+            image_loader.cache_dir().display()
+        );
+    }
+    Ok(())
+}
+
+fn cache(global: &GlobalArgs, command: &CacheCommand) -> Result<()> {
+    let image_loader = image_loader(global, Path::new(""))?;
+    let cache = image_loader.cache();
+    match command {
+        CacheCommand::Dir => println!("{}", cache.dir().display()),
+        CacheCommand::List => {
+            let entries = cache.entries();
+            for (key, entry) in entries.iter() {
+                println!(
+                    "{:>10}  fetched {:<9}  used {:<9}  {}",
+                    format_size(entry.size),
+                    format_age(entry.fetched_at),
+                    format_age(entry.last_accessed),
+                    entry.url
+                );
+                if global.verbose > 0 {
+                    println!("{:>10}  {}  sha256 {}", "", key, entry.sha256);
+                }
+            }
+            let total = entries.iter().map(|(_, entry)| entry.size).sum::<u64>();
+            println!("{} images, {} in total", entries.len(), format_size(total));
+        }
+        CacheCommand::Prune => {
+            let summary = cache.prune()?;
+            if global.verbose > 0 {
+                for key in summary.removed.iter() {
+                    println!("removed {}", key);
+                }
+            }
+            if !global.quiet {
+                println!(
+                    "removed {} images, freed {}",
+                    summary.removed.len(),
+                    format_size(summary.freed)
+                );
+            }
+        }
+        CacheCommand::Clear => {
+            cache.clear()?;
+            if !global.quiet {
+                println!("cleared {}", cache.dir().display());
+            }
+        }
+        CacheCommand::Refresh { urls, all } => {
+            let urls = if *all {
+                cache.entries().into_iter().map(|(_, e)| e.url).collect()
+            } else {
+                urls.clone()
+            };
+            let mut failures = 0;
+            for url in urls.iter() {
+                match image_loader.refresh(url) {
+                    Ok(outcome) if !global.quiet => {
+                        let outcome = match outcome {
+                            RefreshOutcome::NotModified => "up to date",
+                            RefreshOutcome::Updated => "updated",
+                        };
+                        println!("{}: {}", url, outcome);
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        error!(
+                            url = url.as_str(), code = error.code(),
+                            error:% = error.display_chain();
+                            "failed to refresh image"
+                        );
+                        failures += 1;
+                    }
+                }
+            }
+            if failures > 0 {
+                return Err(Error::RefreshFailed {
+                    failed: failures,
+                    total: urls.len(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn init(global: &GlobalArgs, args: &InitArgs) -> Result<()> {
+    if args.output.exists() && !args.force {
+        return Err(Error::FileExists {
+            path: args.output.clone(),
+        });
+    }
+    fs::write(&args.output, BOARD_TEMPLATE).map_err(Error::io(&args.output))?;
+    if !global.quiet {
+        println!("wrote starter board to {}", args.output.display());
+    }
+    Ok(())
+}
+
+/// Creates an image loader that resolves relative paths against the directory of `board_path`.
+fn image_loader(global: &GlobalArgs, board_path: &Path) -> Result<ImageLoader> {
+    ImageLoader::new(ImageLoaderOptions {
+        cache_dir: global.cache_dir.clone(),
+        base_dir: board_path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        max_concurrent_fetches: global.max_fetches as usize,
+        offline: global.offline,
+        cache: CacheOptions {
+            ttl: global.cache_ttl,
+            max_size: global.cache_max_size,
+        },
+        wiki_api_url: global.wiki_api_url.clone(),
+        s3_endpoint: global.s3_endpoint.clone(),
+        http: HttpOptions {
+            timeout: global.timeout,
+            retries: global.retries,
+            rate_limit: Some(global.rate_limit),
+            ..Default::default()
+        },
+    })
+}
+
+/// Parses and validates a board, printing any diagnostics; fails if there were any errors.
+fn check_board(
+    global: &GlobalArgs,
+    path: &Path,
+    image_loader: &ImageLoader,
+    text_renderer: &TextRenderer,
+) -> Result<BoardBuilder> {
+    check_yaml(global, path, |board_builder: &BoardBuilder| {
+        board_builder.validate(image_loader, text_renderer)
+    })
+}
+
+/// Parses and validates a team progress file against a board with `tile_count` tiles.
+fn check_progress(global: &GlobalArgs, path: &Path, tile_count: usize) -> Result<TeamProgress> {
+    check_yaml(global, path, |progress: &TeamProgress| {
+        progress.validate(tile_count)
+    })
+}
+
+/// Parses a YAML file and runs `validate` on the result, printing any diagnostics against the
+/// source; fails if there were any errors.
+fn check_yaml<T: DeserializeOwned>(
+    global: &GlobalArgs,
+    path: &Path,
+    validate: impl FnOnce(&T) -> Vec<Diagnostic>,
+) -> Result<T> {
+    let source = fs::read_to_string(path).map_err(Error::io(path))?;
+    let file_name = path.display().to_string();
+    let report = Report::new(&file_name, &source);
+    let (value, diagnostics) = match serde_yaml::from_str::<T>(&source) {
+        Ok(value) => {
+            let diagnostics = validate(&value);
+            (Some(value), diagnostics)
+        }
+        Err(e) => (None, vec![Diagnostic::from_yaml_error(&e)]),
+    };
+    for diagnostic in diagnostics.iter() {
+        logging::diagnostic(global.log_format, &report, diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    match value {
+        Some(value) if errors == 0 => {
+            if let Some(summary) = report.summary(&diagnostics) {
+                warn!("{}", summary);
+            }
+            Ok(value)
+        }
+        _ => Err(Error::InvalidFile {
+            path: path.to_path_buf(),
+            errors,
+        }),
+    }
+}
+
+/// Lists the YAML files in `dir`, sorted by name.
+fn progress_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).map_err(Error::io(dir))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(Error::io(dir))?.path();
+        let is_yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        if is_yaml && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
@@ -30,6 +30,18 @@ pub const GREEN: Rgba = Rgba {
     b: 28,
     a: 255,
 };
+pub const RED: Rgba = Rgba {
+    r: 255,
+    g: 0,
+    b: 0,
+    a: 255,
+};
+pub const GRAY: Rgba = Rgba {
+    r: 155,
+    g: 155,
+    b: 155,
+    a: 255,
+};
 
 // board-specific colors
 pub const DEFAULT_BORDER_COLOR: Rgba = Rgba {
@@ -56,3 +68,15 @@ pub const DEFAULT_BACKGROUND_UNLOCKED_COLOR: Rgba = Rgba {
     b: 64,
     a: 255,
 };
+pub const DEFAULT_BACKGROUND_HIDDEN_COLOR: Rgba = Rgba {
+    r: 56,
+    g: 48,
+    b: 40,
+    a: 255,
+};
+pub const DEFAULT_BACKGROUND_FAILED_COLOR: Rgba = Rgba {
+    r: 82,
+    g: 48,
+    b: 42,
+    a: 255,
+};
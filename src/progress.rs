@@ -0,0 +1,131 @@
+//! Per-team progress files, which are applied on top of a shared board definition.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{
+    diagnostics::Diagnostic,
+    error::Error,
+    tile::{ProgressCount, TileState},
+};
+
+/// The state of every tile for a single team. Tiles that aren't listed get `default_state`.
+#[derive(Deserialize, Debug, Default)]
+pub struct TeamProgress {
+    /// Display name of the team
+    #[serde(default)]
+    pub team: Option<String>,
+    /// State of tiles that aren't listed in `tiles`, revealed unless set
+    #[serde(default)]
+    pub default_state: TileState,
+    /// Progress keyed by tile number
+    #[serde(default)]
+    pub tiles: BTreeMap<u8, TileProgress>,
+}
+
+/// What a team's progress file says about one tile
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(from = "TileProgressShim")]
+pub struct TileProgress {
+    pub state: TileState,
+    pub progress: Option<ProgressCount>,
+}
+
+/// Allows `4: true` and `4: completed` as shorthand for `4: { state: completed }`, and keeps
+/// accepting the older `4: { unlocked: true }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TileProgressShim {
+    Unlocked(bool),
+    State(TileState),
+    Detailed {
+        state: TileState,
+        #[serde(default)]
+        progress: Option<ProgressCount>,
+    },
+    Legacy {
+        unlocked: bool,
+    },
+}
+
+impl From<TileProgressShim> for TileProgress {
+    fn from(shim: TileProgressShim) -> Self {
+        let (state, progress) = match shim {
+            TileProgressShim::State(state) => (state, None),
+            TileProgressShim::Detailed { state, progress } => (state, progress),
+            TileProgressShim::Unlocked(unlocked) | TileProgressShim::Legacy { unlocked } => {
+                (TileState::from_unlocked(unlocked), None)
+            }
+        };
+        Self { state, progress }
+    }
+}
+
+impl TeamProgress {
+    /// Checks that every tile number refers to a tile on a board with `tile_count` tiles.
+    pub fn validate(&self, tile_count: usize) -> Vec<Diagnostic> {
+        let mut diagnostics = self
+            .tiles
+            .keys()
+            .filter(|&&number| number == 0 || number as usize > tile_count)
+            .map(|&number| {
+                let error = Error::UnexpectedTile {
+                    number,
+                    count: tile_count,
+                };
+                Diagnostic::from_error(&error).at(format!("tiles.{}", number))
+            })
+            .collect::<Vec<_>>();
+        for (number, tile) in self.tiles.iter() {
+            if tile.progress.is_some_and(|p| p.target == 0) {
+                let error = Error::ZeroProgressTarget { number: *number };
+                diagnostics.push(
+                    Diagnostic::from_error(&error).at(format!("tiles.{}.progress.target", number)),
+                );
+            }
+        }
+        diagnostics
+    }
+
+    /// The state this team has the tile numbered `number` in.
+    pub fn state(&self, number: u8) -> TileState {
+        self.tiles
+            .get(&number)
+            .map_or(self.default_state, |p| p.state)
+    }
+
+    /// The partial progress this team has made on the tile numbered `number`, if any.
+    pub fn progress(&self, number: u8) -> Option<ProgressCount> {
+        self.tiles.get(&number).and_then(|p| p.progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_shorthand_and_detailed_tiles() {
+        let progress: TeamProgress = serde_yaml::from_str(
+            "team: Zulrah Enjoyers\ntiles:\n  1: true\n  2: { unlocked: false }\n  3: { unlocked: true }\n  4: failed\n  5: { state: in_progress, progress: { current: 2, target: 3 } }\n",
+        )
+        .expect("expected parse to succeed");
+        assert_eq!(progress.team.as_deref(), Some("Zulrah Enjoyers"));
+        assert_eq!(progress.state(1), TileState::Completed);
+        assert_eq!(progress.state(2), TileState::Revealed);
+        assert_eq!(progress.state(3), TileState::Completed);
+        assert_eq!(progress.state(4), TileState::Failed);
+        assert_eq!(progress.state(5), TileState::InProgress);
+        assert_eq!(progress.state(6), TileState::Revealed);
+        assert_eq!(
+            progress.progress(5),
+            Some(ProgressCount {
+                current: 2,
+                target: 3
+            })
+        );
+        assert_eq!(progress.validate(5).len(), 0);
+        assert_eq!(progress.validate(4).len(), 1);
+    }
+}
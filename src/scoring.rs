@@ -0,0 +1,207 @@
+//! Bingo scoring: completed tiles, lines and blackouts.
+
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{board::Board, builder::BoardBuilder, progress::TeamProgress, tile::TileState};
+
+/// How many points each achievement is worth.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScoringOptions {
+    pub points_per_tile: u32,
+    pub points_per_line: u32,
+    /// Awarded on top of everything else when every tile is completed
+    pub blackout_bonus: u32,
+}
+
+impl Default for ScoringOptions {
+    fn default() -> Self {
+        Self {
+            points_per_tile: 1,
+            points_per_line: 5,
+            blackout_bonus: 0,
+        }
+    }
+}
+
+/// A row, column or diagonal of the board. Diagonals only exist on square boards.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", content = "index", rename_all = "snake_case")]
+pub enum Line {
+    Row(usize),
+    Column(usize),
+    /// Top left to bottom right
+    Diagonal,
+    /// Top right to bottom left
+    AntiDiagonal,
+}
+
+impl Line {
+    /// Every line on a `rows` x `cols` board.
+    pub fn all(rows: usize, cols: usize) -> Vec<Line> {
+        let mut lines = (0..rows).map(Line::Row).collect::<Vec<_>>();
+        lines.extend((0..cols).map(Line::Column));
+        if rows == cols && rows > 1 {
+            lines.push(Line::Diagonal);
+            lines.push(Line::AntiDiagonal);
+        }
+        lines
+    }
+
+    /// Indices of the tiles on this line, in row-major order, from one end to the other.
+    pub fn tile_indices(&self, rows: usize, cols: usize) -> Vec<usize> {
+        match *self {
+            Line::Row(row) => (0..cols).map(|col| row * cols + col).collect(),
+            Line::Column(col) => (0..rows).map(|row| row * cols + col).collect(),
+            Line::Diagonal => (0..rows).map(|i| i * cols + i).collect(),
+            Line::AntiDiagonal => (0..rows).map(|i| i * cols + (cols - 1 - i)).collect(),
+        }
+    }
+}
+
+/// A board's score, and what it was made up of
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    pub tiles_completed: usize,
+    pub tiles_total: usize,
+    /// Every completed line
+    pub lines: Vec<Line>,
+    pub blackout: bool,
+    pub points: u32,
+}
+
+impl Score {
+    /// Scores a `rows` x `cols` board, where `completed` holds the state of each tile in
+    /// row-major order.
+    pub fn compute(rows: usize, cols: usize, completed: &[bool], options: &ScoringOptions) -> Self {
+        let tiles_completed = completed.iter().filter(|&&c| c).count();
+        let tiles_total = completed.len();
+        let lines = Line::all(rows, cols)
+            .into_iter()
+            .filter(|line| {
+                line.tile_indices(rows, cols)
+                    .iter()
+                    .all(|&i| completed.get(i).copied().unwrap_or(false))
+            })
+            .collect::<Vec<_>>();
+        let blackout = tiles_total > 0 && tiles_completed == tiles_total;
+        // absurd point values max the score out rather than wrapping around to a small one
+        let count = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+        let mut points = count(tiles_completed)
+            .saturating_mul(options.points_per_tile)
+            .saturating_add(count(lines.len()).saturating_mul(options.points_per_line));
+        if blackout {
+            points = points.saturating_add(options.blackout_bonus);
+        }
+        Self {
+            tiles_completed,
+            tiles_total,
+            lines,
+            blackout,
+            points,
+        }
+    }
+
+    pub fn for_board(board: &Board) -> Self {
+        let completed = board
+            .tiles
+            .iter()
+            .map(|t| t.state == TileState::Completed)
+            .collect::<Vec<_>>();
+        Self::compute(board.rows, board.cols, &completed, &board.scoring)
+    }
+
+    /// Scores a board definition without loading any images; tile states come from `progress`
+    /// when it is given.
+    pub fn for_board_builder(builder: &BoardBuilder, progress: Option<&TeamProgress>) -> Self {
+        let mut tiles = builder.tiles.iter().collect::<Vec<_>>();
+        tiles.sort_by_key(|t| t.number);
+        let completed = tiles
+            .iter()
+            .map(|t| match progress {
+                Some(progress) => progress.state(t.number),
+                None => t.state(),
+            })
+            .map(|state| state == TileState::Completed)
+            .collect::<Vec<_>>();
+        Self::compute(builder.rows, builder.cols, &completed, &builder.scoring)
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 1-based for humans
+        match self {
+            Line::Row(i) => write!(f, "row {}", i + 1),
+            Line::Column(i) => write!(f, "column {}", i + 1),
+            Line::Diagonal => write!(f, "diagonal"),
+            Line::AntiDiagonal => write!(f, "anti-diagonal"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_rows_columns_and_diagonals() {
+        #[rustfmt::skip]
+        let completed = [
+            true,  true,  true,
+            false, true,  true,
+            true,  false, true,
+        ];
+        let score = Score::compute(3, 3, &completed, &ScoringOptions::default());
+        assert_eq!(score.tiles_completed, 7);
+        assert_eq!(
+            score.lines,
+            vec![
+                Line::Row(0),
+                Line::Column(2),
+                Line::Diagonal,
+                Line::AntiDiagonal
+            ]
+        );
+        assert!(!score.blackout);
+        assert_eq!(score.points, 7 + 4 * 5);
+    }
+
+    #[test]
+    fn it_awards_the_blackout_bonus() {
+        let options = ScoringOptions {
+            points_per_tile: 2,
+            points_per_line: 10,
+            blackout_bonus: 100,
+        };
+        let score = Score::compute(1, 2, &[true, true], &options);
+        // no diagonals on a non-square board
+        assert_eq!(
+            score.lines,
+            vec![Line::Row(0), Line::Column(0), Line::Column(1)]
+        );
+        assert!(score.blackout);
+        assert_eq!(score.points, 2 * 2 + 3 * 10 + 100);
+    }
+
+    #[test]
+    fn it_saturates_huge_scores() {
+        let options = ScoringOptions {
+            points_per_tile: u32::MAX / 2,
+            points_per_line: 0,
+            blackout_bonus: 0,
+        };
+        let score = Score::compute(2, 2, &[true, true, true, false], &options);
+        assert_eq!(score.points, u32::MAX);
+        let options = ScoringOptions {
+            points_per_tile: 1,
+            points_per_line: 1,
+            blackout_bonus: u32::MAX,
+        };
+        let score = Score::compute(2, 2, &[true; 4], &options);
+        assert!(score.blackout);
+        assert_eq!(score.points, u32::MAX);
+    }
+}
@@ -0,0 +1,416 @@
+//! Image sources that are picked by the scheme at the start of a location, like
+//! `wiki:Serpentine helm`. Paths and http(s), `file:` and `data:` URLs are handled by
+//! [`ImageLoader`] itself, every other scheme goes to the source registered for it.
+
+use log::{debug, warn};
+use ril::{Image, Rgba};
+use url::Url;
+
+use crate::{error::Error, images::ImageLoader, palette::GRAY};
+
+/// Loads images for one scheme. Sources get the loader they're registered with, so that they
+/// can build on the locations it already knows how to load, and its cache.
+pub trait ImageSource: Send + Sync {
+    /// The scheme this source handles, without the `:`
+    fn scheme(&self) -> &str;
+
+    /// Loads the image for `reference`, which is the location without `<scheme>:`.
+    fn load(&self, reference: &str, loader: &ImageLoader) -> Result<Image<Rgba>, Error>;
+
+    /// Checks that `reference` could be loaded without touching the network.
+    fn check(&self, _reference: &str, _loader: &ImageLoader) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Whether loading `reference` would need the network, which offline mode rules out.
+    fn needs_network(&self, _reference: &str, _loader: &ImageLoader) -> bool {
+        false
+    }
+
+    /// The dimensions of the image, if they're known without touching the network.
+    fn cached_dimensions(
+        &self,
+        _reference: &str,
+        _loader: &ImageLoader,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        Ok(None)
+    }
+}
+
+/// Turns an item name into the URL of an image of that item, see `wiki::WikiResolver` for the
+/// one that asks the OSRS Wiki.
+pub trait ItemImageResolver: Send + Sync {
+    /// Returns `None` if there's no image for the item.
+    fn resolve(&self, item: &str) -> Result<Option<String>, Error>;
+}
+
+/// `wiki:<item name>`, the item's image as found by an [`ItemImageResolver`]. What each item
+/// resolved to is kept in the image cache.
+pub struct WikiSource {
+    resolver: Box<dyn ItemImageResolver>,
+}
+
+impl WikiSource {
+    pub fn new(resolver: Box<dyn ItemImageResolver>) -> Self {
+        Self { resolver }
+    }
+
+    /// Looks up the image URL for an item, asking the resolver only if it hasn't been looked
+    /// up before or the answer has outlived the cache TTL.
+    fn item_url(&self, name: &str, loader: &ImageLoader) -> Result<String, Error> {
+        let cache = loader.cache();
+        let cached = cache.item(name);
+        if let Some(item) = &cached {
+            if loader.is_offline() || !cache.is_item_stale(item) {
+                return Ok(item.url.clone());
+            }
+        }
+        if loader.is_offline() {
+            return Err(Error::NotCached {
+                locations: vec![format!("wiki:{}", name)],
+            });
+        }
+        let resolved = self.resolver.resolve(name);
+        debug!(item = name; "looked up wiki item");
+        match (resolved, cached) {
+            (Ok(Some(url)), _) => {
+                cache.store_item(name, &url)?;
+                Ok(url)
+            }
+            // the wiki being down shouldn't break a board that was looked up before
+            (Err(e), Some(item)) => {
+                warn!(item = name, error:% = e.display_chain(); "couldn't look up wiki item, using the cached image");
+                Ok(item.url)
+            }
+            (Ok(None), _) => Err(Error::ItemNotFound {
+                item: name.to_string(),
+            }),
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+impl ImageSource for WikiSource {
+    fn scheme(&self) -> &str {
+        "wiki"
+    }
+
+    fn load(&self, reference: &str, loader: &ImageLoader) -> Result<Image<Rgba>, Error> {
+        let url = self.item_url(reference.trim(), loader)?;
+        loader.load_from_url(url)
+    }
+
+    fn check(&self, reference: &str, _loader: &ImageLoader) -> Result<(), Error> {
+        if reference.trim().is_empty() {
+            return Err(Error::invalid_location(
+                &format!("wiki:{}", reference),
+                "the item name must not be empty",
+            ));
+        }
+        Ok(())
+    }
+
+    fn needs_network(&self, reference: &str, loader: &ImageLoader) -> bool {
+        match loader.cache().item(reference.trim()) {
+            Some(item) => loader.url_needs_network(&item.url),
+            None => true,
+        }
+    }
+
+    fn cached_dimensions(
+        &self,
+        reference: &str,
+        loader: &ImageLoader,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        match loader.cache().item(reference.trim()) {
+            Some(item) => loader.cached_url_dimensions(&item.url),
+            None => Ok(None),
+        }
+    }
+}
+
+/// `s3:<bucket>/<key>`, an object in an S3-compatible bucket that allows anonymous reads,
+/// fetched and cached like any other URL.
+pub struct S3Source {
+    endpoint: Url,
+}
+
+impl S3Source {
+    pub fn new(endpoint: Url) -> Self {
+        Self { endpoint }
+    }
+
+    /// The path-style URL of the object, which every S3-compatible service supports.
+    fn url(&self, reference: &str) -> Result<String, Error> {
+        match reference.trim_start_matches('/').split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(format!(
+                "{}/{}/{}",
+                self.endpoint.as_str().trim_end_matches('/'),
+                bucket,
+                key
+            )),
+            _ => Err(Error::invalid_location(
+                &format!("s3:{}", reference),
+                "must look like s3:<bucket>/<key>",
+            )),
+        }
+    }
+}
+
+impl ImageSource for S3Source {
+    fn scheme(&self) -> &str {
+        "s3"
+    }
+
+    fn load(&self, reference: &str, loader: &ImageLoader) -> Result<Image<Rgba>, Error> {
+        loader.load_from_url(self.url(reference)?)
+    }
+
+    fn check(&self, reference: &str, _loader: &ImageLoader) -> Result<(), Error> {
+        self.url(reference).map(|_| ())
+    }
+
+    fn needs_network(&self, reference: &str, loader: &ImageLoader) -> bool {
+        self.url(reference)
+            .is_ok_and(|url| loader.url_needs_network(&url))
+    }
+
+    fn cached_dimensions(
+        &self,
+        reference: &str,
+        loader: &ImageLoader,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        loader.cached_url_dimensions(&self.url(reference)?)
+    }
+}
+
+/// `atlas:<location>#<x>,<y>,<width>,<height>`, a region of a sprite sheet. The sheet can be
+/// any other location, including relative paths.
+pub struct AtlasSource;
+
+/// A parsed `atlas:` reference
+#[derive(Debug, PartialEq, Eq)]
+struct AtlasRegion<'a> {
+    sheet: &'a str,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl AtlasSource {
+    fn parse(reference: &str) -> Result<AtlasRegion<'_>, Error> {
+        let invalid = || {
+            Error::invalid_location(
+                &format!("atlas:{}", reference),
+                "must look like atlas:<location>#<x>,<y>,<width>,<height>",
+            )
+        };
+        let Some((sheet, region)) = reference.rsplit_once('#') else {
+            return Err(invalid());
+        };
+        let numbers = region
+            .split(',')
+            .map(|n| n.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>();
+        match numbers.as_deref() {
+            Ok(&[x, y, width, height]) if width > 0 && height > 0 && !sheet.is_empty() => {
+                Ok(AtlasRegion {
+                    sheet,
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl ImageSource for AtlasSource {
+    fn scheme(&self) -> &str {
+        "atlas"
+    }
+
+    fn load(&self, reference: &str, loader: &ImageLoader) -> Result<Image<Rgba>, Error> {
+        let AtlasRegion {
+            sheet,
+            x,
+            y,
+            width,
+            height,
+        } = Self::parse(reference)?;
+        let image = loader.load(sheet)?;
+        if x.saturating_add(width) > image.width() || y.saturating_add(height) > image.height() {
+            return Err(Error::invalid_location(
+                &format!("atlas:{}", reference),
+                format!(
+                    "the region must be inside the {}x{} sprite sheet",
+                    image.width(),
+                    image.height()
+                ),
+            ));
+        }
+        Ok(image.cropped(x, y, x + width, y + height))
+    }
+
+    fn check(&self, reference: &str, loader: &ImageLoader) -> Result<(), Error> {
+        loader.check(Self::parse(reference)?.sheet)
+    }
+
+    fn needs_network(&self, reference: &str, loader: &ImageLoader) -> bool {
+        Self::parse(reference).is_ok_and(|region| loader.needs_network(region.sheet))
+    }
+
+    fn cached_dimensions(
+        &self,
+        reference: &str,
+        _loader: &ImageLoader,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        let region = Self::parse(reference)?;
+        Ok(Some((region.width, region.height)))
+    }
+}
+
+/// `placeholder:` or `placeholder:<color>`, a checkerboard to stand in for images that
+/// haven't been picked yet.
+pub struct PlaceholderSource;
+
+impl PlaceholderSource {
+    const SIZE: u32 = 128;
+    const SQUARE_SIZE: u32 = 16;
+
+    fn color(reference: &str) -> Result<Rgba, Error> {
+        match reference.trim() {
+            "" => Ok(GRAY),
+            color => Rgba::from_hex(color).map_err(|_| {
+                Error::invalid_location(
+                    &format!("placeholder:{}", reference),
+                    "must be a hex color like #808080",
+                )
+            }),
+        }
+    }
+}
+
+impl ImageSource for PlaceholderSource {
+    fn scheme(&self) -> &str {
+        "placeholder"
+    }
+
+    fn load(&self, reference: &str, _loader: &ImageLoader) -> Result<Image<Rgba>, Error> {
+        let color = Self::color(reference)?;
+        let dark = Rgba {
+            r: color.r / 2,
+            g: color.g / 2,
+            b: color.b / 2,
+            a: color.a,
+        };
+        Ok(Image::from_fn(Self::SIZE, Self::SIZE, |x, y| {
+            if (x / Self::SQUARE_SIZE + y / Self::SQUARE_SIZE) % 2 == 0 {
+                color
+            } else {
+                dark
+            }
+        }))
+    }
+
+    fn check(&self, reference: &str, _loader: &ImageLoader) -> Result<(), Error> {
+        Self::color(reference).map(|_| ())
+    }
+
+    fn cached_dimensions(
+        &self,
+        reference: &str,
+        _loader: &ImageLoader,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        Self::color(reference)?;
+        Ok(Some((Self::SIZE, Self::SIZE)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "network")]
+    use std::{
+        io::{BufRead, BufReader, Cursor, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+    #[cfg(feature = "network")]
+    use crate::{images::ImageLoaderOptions, test_util::TempDir};
+
+    #[test]
+    fn it_parses_atlas_regions() {
+        let region = AtlasSource::parse("sheets/items.png#32,64,32,32")
+            .expect("expected the region to parse");
+        assert_eq!(
+            region,
+            AtlasRegion {
+                sheet: "sheets/items.png",
+                x: 32,
+                y: 64,
+                width: 32,
+                height: 32
+            }
+        );
+        // the last '#' starts the region, so URLs with fragments still work
+        let region = AtlasSource::parse("https://example.com/a.png#top#0,0,1,1").unwrap();
+        assert_eq!(region.sheet, "https://example.com/a.png#top");
+        assert!(AtlasSource::parse("items.png#0,0,0,32").is_err());
+        assert!(AtlasSource::parse("items.png").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn it_keeps_the_s3_endpoint_scheme() {
+        // a one-shot fixture server standing in for a local S3-compatible store
+        let listener = TcpListener::bind("127.0.0.1:0").expect("expected to bind");
+        let endpoint = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(2, 1))
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("expected a request");
+            let mut request_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .expect("expected a request line");
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                png.len()
+            )
+            .expect("expected to respond");
+            stream.write_all(&png).expect("expected to respond");
+            request_line
+        });
+        let source = S3Source::new(endpoint.clone());
+        let url = source.url("bucket/helm.png").unwrap();
+        assert_eq!(url, format!("{}bucket/helm.png", endpoint));
+        let dir = TempDir::new("s3");
+        let image_loader = ImageLoader::new(ImageLoaderOptions {
+            cache_dir: dir.path().to_path_buf(),
+            s3_endpoint: endpoint,
+            ..Default::default()
+        })
+        .unwrap();
+        let image = image_loader
+            .load("s3:bucket/helm.png")
+            .expect("expected the object to load over http");
+        assert_eq!(image.dimensions(), (2, 1));
+        assert!(server.join().unwrap().starts_with("GET /bucket/helm.png "));
+        // and it's found in the cache under the same URL
+        assert!(!image_loader.needs_network("s3:bucket/helm.png"));
+        assert_eq!(
+            image_loader
+                .cached_dimensions("s3:bucket/helm.png")
+                .unwrap(),
+            Some((2, 1))
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! Helpers shared by the tests in the other modules.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// An empty directory for a test to work in, removed again when it's dropped. `name` keeps
+/// tests that run in parallel out of each other's way.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("boardbuilder-{}-test-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("expected to create a temp dir");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// `name` inside the directory.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
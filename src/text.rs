@@ -1,13 +1,16 @@
+//! Drawing text in a font with a drop shadow, and wrapping and shrinking it to fit a width.
+
 use ril::{Font, Image, OverlayMode, Paste, Rgba, TextLayout, TextSegment, WrapStyle};
 
 use crate::{
-    error::AppError,
+    error::Error,
     palette::{BLACK, TRANSPARENT, YELLOW},
 };
 
 const DEFAULT_FONT_BYTES: &[u8] =
     include_bytes!("../assets/fonts/runescape-chat-bold-2/runescape-chat-bold-2.otf");
 
+/// Renders text in the RuneScape chat font
 pub struct TextRenderer {
     font: Font,
 }
@@ -32,6 +35,16 @@ impl Default for TextRenderOptions {
     }
 }
 
+/// Text that has been wrapped to fit a width, see [`TextRenderer::fit`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FittedText {
+    pub lines: Vec<String>,
+    /// The font size the lines were wrapped at
+    pub size: f32,
+    /// Whether the lines fit, if not they were wrapped at the minimum size anyway
+    pub fits: bool,
+}
+
 /// Options for pixelating text
 pub struct TextPixelationOptions {
     /// Antialiased pixels less-than or equal-to this alpha value will become
@@ -52,17 +65,104 @@ impl TextRenderer {
         Self { font }
     }
 
-    pub fn from_font_bytes(font_bytes: &[u8], optimal_size: f32) -> Result<Self, AppError> {
-        let font = Font::from_bytes(font_bytes, optimal_size).map_err(AppError::RILError)?;
+    /// Loads a TrueType or OpenType font. `optimal_size` is the size glyphs are rasterized at
+    /// and scaled from, which for pixel fonts should be the size they were drawn at.
+    pub fn from_font_bytes(font_bytes: &[u8], optimal_size: f32) -> Result<Self, Error> {
+        let font = Font::from_bytes(font_bytes, optimal_size).map_err(Error::from)?;
         Ok(Self::from_font(font))
     }
 
+    /// Computes the dimensions of the image `render` would produce, without rendering it.
+    pub fn measure(&self, text: impl AsRef<str>, options: &TextRenderOptions) -> (u32, u32) {
+        let (width, height) = self.layout(text, options).dimensions();
+        // +1 for the shadow, see `render`
+        (width + 1, height + 1)
+    }
+
+    /// Greedily wraps `text` on whitespace so that each line is at most `max_width` wide. A word
+    /// that is wider than that on its own gets a line to itself.
+    pub fn wrap(&self, text: &str, max_width: u32, options: &TextRenderOptions) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for word in text.split_whitespace() {
+            if line.is_empty() {
+                line.push_str(word);
+                continue;
+            }
+            let candidate = format!("{} {}", line, word);
+            if self.measure(&candidate, options).0 <= max_width {
+                line = candidate;
+            } else {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Wraps `text` into at most `max_lines` lines of `max_width`, shrinking the font one point
+    /// at a time from `options.size` down to `min_size` until it does.
+    pub fn fit(
+        &self,
+        text: &str,
+        max_width: u32,
+        max_lines: usize,
+        min_size: f32,
+        options: &TextRenderOptions,
+    ) -> FittedText {
+        let mut size = options.size;
+        loop {
+            let sized = TextRenderOptions {
+                size,
+                color: options.color,
+                pixelation: None,
+            };
+            let lines = self.wrap(text, max_width, &sized);
+            let fits = lines.len() <= max_lines
+                && lines
+                    .iter()
+                    .all(|line| self.measure(line, &sized).0 <= max_width);
+            if fits || size - 1.0 < min_size {
+                return FittedText { lines, size, fits };
+            }
+            size -= 1.0;
+        }
+    }
+
+    /// Renders `lines` one under the other, each centered horizontally.
+    pub fn render_lines(&self, lines: &[String], options: &TextRenderOptions) -> Image<Rgba> {
+        if let [line] = lines {
+            return self.render(line, options);
+        }
+        let images = lines
+            .iter()
+            .map(|line| self.render(line, options))
+            .collect::<Vec<_>>();
+        let width = images.iter().map(Image::width).max().unwrap_or(0);
+        let line_height = images.iter().map(Image::height).max().unwrap_or(0);
+        let mut image = Image::new(
+            width.max(1),
+            (line_height * images.len() as u32).max(1),
+            TRANSPARENT,
+        );
+        for (i, line) in images.iter().enumerate() {
+            image.draw(&Paste {
+                position: ((width - line.width()) / 2, i as u32 * line_height),
+                image: line,
+                mask: None,
+                overlay: Some(OverlayMode::Merge),
+            });
+        }
+        image
+    }
+
+    /// Renders `text` on a single line with a 1px shadow below and to the right, on a
+    /// transparent image just big enough for it.
     pub fn render(&self, text: impl AsRef<str>, options: &TextRenderOptions) -> Image<Rgba> {
         // render text as pure black first
-        let layout = TextLayout::new()
-            .with_position(0, 0)
-            .with_wrap(WrapStyle::None)
-            .with_segment(&TextSegment::new(&self.font, text, BLACK));
+        let layout = self.layout(text, options);
 
         // to accomodate the shadow under the text, add +1 to the dimensions
         let (mut text_width, mut text_height) = layout.dimensions();
@@ -103,6 +203,13 @@ impl TextRenderer {
 
         text_image
     }
+
+    fn layout(&self, text: impl AsRef<str>, options: &TextRenderOptions) -> TextLayout<'_, Rgba> {
+        TextLayout::new()
+            .with_position(0, 0)
+            .with_wrap(WrapStyle::None)
+            .with_segment(&TextSegment::new(&self.font, text, BLACK).with_size(options.size))
+    }
 }
 
 /// Applies an alpha threshold to an image in-place; pixels whose alpha value is
@@ -120,11 +227,33 @@ fn alpha_threshold(image: &mut Image<Rgba>, cutoff: u8) {
 
 #[cfg(test)]
 mod tests {
-    use super::TextRenderer;
+    use super::{TextRenderOptions, TextRenderer};
 
     #[test]
     fn it_loads_default() {
         // no panic here means that this platform can load the default font
         TextRenderer::default();
     }
+
+    #[test]
+    fn it_wraps_and_shrinks_to_fit() {
+        let renderer = TextRenderer::default();
+        let options = TextRenderOptions {
+            size: 20.0,
+            ..Default::default()
+        };
+        let (width, _) = renderer.measure("Tombs of Amascut", &options);
+        let fitted = renderer.fit("Tombs of Amascut purple", width, 2, 12.0, &options);
+        assert!(fitted.fits);
+        assert_eq!(fitted.size, 20.0);
+        assert_eq!(fitted.lines, vec!["Tombs of Amascut", "purple"]);
+        // a single line only fits once the text is shrunk
+        let fitted = renderer.fit("Tombs of Amascut purple", width, 1, 12.0, &options);
+        assert!(fitted.fits);
+        assert!(fitted.size < 20.0);
+        // and nothing fits in 1px
+        let fitted = renderer.fit("Tombs", 1, 2, 12.0, &options);
+        assert!(!fitted.fits);
+        assert_eq!(fitted.size, 12.0);
+    }
 }
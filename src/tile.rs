@@ -1,10 +1,16 @@
+//! Tiles as they're drawn: their state, progress and images, the options and themes they're
+//! rendered with, and the [`TileRenderer`] that draws one.
+
+use std::fmt::{self, Display};
+
 use ril::{Border, BorderPosition, Image, OverlayMode, Paste, Rectangle, ResizeAlgorithm, Rgba};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     palette::{
+        DEFAULT_BACKGROUND_FAILED_COLOR, DEFAULT_BACKGROUND_HIDDEN_COLOR,
         DEFAULT_BACKGROUND_LOCKED_COLOR, DEFAULT_BACKGROUND_UNLOCKED_COLOR, DEFAULT_BORDER_COLOR,
-        DEFAULT_INSET_COLOR, GREEN, ORANGE,
+        DEFAULT_INSET_COLOR, GRAY, GREEN, ORANGE, RED, YELLOW,
     },
     text::{TextRenderOptions, TextRenderer},
 };
@@ -12,18 +18,216 @@ use crate::{
 pub struct Tile {
     pub number: u8,
     pub name: String,
-    pub image: Image<Rgba>,
-    pub unlocked: bool,
+    /// At least one, laid out according to `image_options.layout` when there are several
+    pub images: Vec<Image<Rgba>>,
+    pub image_options: ImageOptions,
+    pub state: TileState,
+    pub progress: Option<ProgressCount>,
+}
+
+/// Partial progress towards a tile, e.g. 2 of 3 drops or 400k of 1M XP
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressCount {
+    pub current: u32,
+    pub target: u32,
+}
+
+impl ProgressCount {
+    /// How far along the tile is, from 0.0 to 1.0
+    pub fn fraction(&self) -> f32 {
+        if self.target == 0 {
+            return 1.0;
+        }
+        (self.current as f32 / self.target as f32).clamp(0.0, 1.0)
+    }
+}
+
+impl Display for ProgressCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", compact(self.current), compact(self.target))
+    }
+}
+
+/// Shortens big numbers so that counters fit on a tile, e.g. 1500000 becomes "1.5M".
+fn compact(n: u32) -> String {
+    let (value, suffix) = match n {
+        0..=9_999 => return n.to_string(),
+        10_000..=999_999 => (n as f32 / 1e3, "k"),
+        1_000_000..=999_999_999 => (n as f32 / 1e6, "M"),
+        _ => (n as f32 / 1e9, "B"),
+    };
+    // one decimal at most, and none if it'd be a zero
+    let value = format!("{:.1}", (value * 10.0).floor() / 10.0);
+    format!("{}{}", value.trim_end_matches(".0"), suffix)
+}
+
+/// Where a team is at with a tile; each state is drawn with its own theme.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TileState {
+    /// Not revealed to players yet, the name and image are hidden
+    Hidden,
+    /// Visible but not started, this is what "locked" used to mean
+    #[default]
+    Revealed,
+    InProgress,
+    /// Done, this is what "unlocked" used to mean
+    Completed,
+    Failed,
+}
+
+impl TileState {
+    /// Every state, in the order legends list them
+    pub const ALL: [TileState; 5] = [
+        TileState::Hidden,
+        TileState::Revealed,
+        TileState::InProgress,
+        TileState::Completed,
+        TileState::Failed,
+    ];
+
+    /// The state's name in legends, e.g. "In progress".
+    pub fn label(&self) -> &'static str {
+        match self {
+            TileState::Hidden => "Hidden",
+            TileState::Revealed => "Not started",
+            TileState::InProgress => "In progress",
+            TileState::Completed => "Completed",
+            TileState::Failed => "Failed",
+        }
+    }
+
+    /// Maps the legacy `unlocked` flag onto a state.
+    pub fn from_unlocked(unlocked: bool) -> Self {
+        if unlocked {
+            TileState::Completed
+        } else {
+            TileState::Revealed
+        }
+    }
+}
+
+/// What happens to a tile's image before it is drawn.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageTreatment {
+    None,
+    /// Blends towards grayscale by the given factor, from 0.0 to 1.0
+    Desaturate(f32),
+    /// Blends towards black by the given factor, from 0.0 to 1.0
+    Darken(f32),
+    /// The image isn't drawn at all
+    Hide,
+}
+
+impl ImageTreatment {
+    pub fn default_for(state: TileState) -> Self {
+        match state {
+            TileState::Hidden => ImageTreatment::Hide,
+            TileState::Revealed => ImageTreatment::Desaturate(0.9),
+            TileState::InProgress => ImageTreatment::Desaturate(0.4),
+            TileState::Completed => ImageTreatment::None,
+            TileState::Failed => ImageTreatment::Darken(0.6),
+        }
+    }
+}
+
+/// How an image is sized to the space left for it on a tile
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFit {
+    /// As big as fits without cropping, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Fills the space, keeping the aspect ratio and cropping whatever sticks out
+    Cover,
+    /// Stretched to exactly the size of the space
+    Fill,
+    /// Drawn at its own size, cropped if it's too big
+    None,
+}
+
+/// The filter images are resized with
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    /// Keeps pixel art sharp
+    Nearest,
+    Bilinear,
+    #[default]
+    Bicubic,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for ResizeAlgorithm {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => ResizeAlgorithm::Nearest,
+            ResizeFilter::Bilinear => ResizeAlgorithm::Bilinear,
+            ResizeFilter::Bicubic => ResizeAlgorithm::Bicubic,
+            ResizeFilter::Lanczos3 => ResizeAlgorithm::Lanczos3,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// How the images of a tile with several are arranged
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageLayout {
+    /// Side by side
+    #[default]
+    Row,
+    /// As many rows and columns as gives the biggest images
+    Grid,
+    /// Side by side, each one half covering the one before it
+    Fan,
+}
+
+/// What's done to a tile's images to fit them onto the tile, in order: trimming, padding, then
+/// scaling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageOptions {
+    /// Crops away fully transparent rows and columns around each image
+    pub trim: bool,
+    /// Space kept clear around the images, in pixels
+    pub padding: u32,
+    pub layout: ImageLayout,
+    /// Space between images in a row or grid, in pixels
+    pub gap: u32,
+    pub fit: ImageFit,
+    /// Scales images that are smaller than the space up, not just big ones down
+    pub upscale: bool,
+    pub resize: ResizeFilter,
+    /// The most an image is scaled up by, e.g. 4.0 so that icons stay recognizable
+    pub max_scale: Option<f32>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            trim: false,
+            padding: 0,
+            layout: ImageLayout::Row,
+            gap: 4,
+            fit: ImageFit::Contain,
+            upscale: false,
+            resize: ResizeFilter::Bicubic,
+            max_scale: None,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct TileRenderOptions {
     pub padding: u32,
     pub border_size: u32,
     pub inset_size: u32,
     pub text_size: u32,
-    pub locked_theme: TileTheme,
-    pub unlocked_theme: TileTheme,
+    /// Names that don't fit are shrunk down to this size before giving up
+    pub min_text_size: u32,
+    /// How many lines a name can wrap onto
+    pub max_name_lines: usize,
+    pub themes: TileThemes,
 }
 
 impl Default for TileRenderOptions {
@@ -33,59 +237,88 @@ impl Default for TileRenderOptions {
             border_size: 4,
             inset_size: 4,
             text_size: 20,
-            locked_theme: TileTheme {
-                border_color: DEFAULT_BORDER_COLOR,
-                inset_color: DEFAULT_INSET_COLOR,
-                background_color: DEFAULT_BACKGROUND_LOCKED_COLOR,
-                text_color: ORANGE,
-            },
-            unlocked_theme: TileTheme {
-                border_color: DEFAULT_BORDER_COLOR,
-                inset_color: DEFAULT_INSET_COLOR,
-                background_color: DEFAULT_BACKGROUND_UNLOCKED_COLOR,
-                text_color: GREEN,
-            },
+            min_text_size: 12,
+            max_name_lines: 2,
+            themes: TileThemes::default(),
         }
     }
 }
 
+/// One theme per tile state
 #[derive(Debug)]
-pub struct TileTheme {
-    pub border_color: Rgba,
-    pub inset_color: Rgba,
-    pub background_color: Rgba,
-    pub text_color: Rgba,
+pub struct TileThemes {
+    pub hidden: TileTheme,
+    pub revealed: TileTheme,
+    pub in_progress: TileTheme,
+    pub completed: TileTheme,
+    pub failed: TileTheme,
 }
 
-// this is super hacky but it gets the job done
-#[derive(Deserialize)]
-struct TileThemeShim {
-    border_color: String,
-    inset_color: String,
-    background_color: String,
-    text_color: String,
-}
-
-impl<'de> Deserialize<'de> for TileTheme {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        use serde::de::Error as DError;
-        let shim = TileThemeShim::deserialize(deserializer)?;
-        let border_color = Rgba::from_hex(&shim.border_color).map_err(DError::custom)?;
-        let inset_color = Rgba::from_hex(&shim.inset_color).map_err(DError::custom)?;
-        let background_color = Rgba::from_hex(&shim.background_color).map_err(DError::custom)?;
-        let text_color = Rgba::from_hex(&shim.text_color).map_err(DError::custom)?;
-        Ok(TileTheme {
-            border_color,
-            inset_color,
+impl TileThemes {
+    pub fn get(&self, state: TileState) -> &TileTheme {
+        match state {
+            TileState::Hidden => &self.hidden,
+            TileState::Revealed => &self.revealed,
+            TileState::InProgress => &self.in_progress,
+            TileState::Completed => &self.completed,
+            TileState::Failed => &self.failed,
+        }
+    }
+
+    pub fn get_mut(&mut self, state: TileState) -> &mut TileTheme {
+        match state {
+            TileState::Hidden => &mut self.hidden,
+            TileState::Revealed => &mut self.revealed,
+            TileState::InProgress => &mut self.in_progress,
+            TileState::Completed => &mut self.completed,
+            TileState::Failed => &mut self.failed,
+        }
+    }
+}
+
+impl Default for TileThemes {
+    fn default() -> Self {
+        let theme = |state, background_color, text_color| TileTheme {
+            border_color: DEFAULT_BORDER_COLOR,
+            inset_color: DEFAULT_INSET_COLOR,
             background_color,
             text_color,
-        })
+            progress_bar_color: text_color,
+            progress_track_color: DEFAULT_INSET_COLOR,
+            image_treatment: ImageTreatment::default_for(state),
+        };
+        Self {
+            hidden: theme(TileState::Hidden, DEFAULT_BACKGROUND_HIDDEN_COLOR, GRAY),
+            revealed: theme(TileState::Revealed, DEFAULT_BACKGROUND_LOCKED_COLOR, ORANGE),
+            in_progress: theme(
+                TileState::InProgress,
+                DEFAULT_BACKGROUND_LOCKED_COLOR,
+                YELLOW,
+            ),
+            completed: theme(
+                TileState::Completed,
+                DEFAULT_BACKGROUND_UNLOCKED_COLOR,
+                GREEN,
+            ),
+            failed: theme(TileState::Failed, DEFAULT_BACKGROUND_FAILED_COLOR, RED),
+        }
     }
 }
 
+#[derive(Debug)]
+pub struct TileTheme {
+    pub border_color: Rgba,
+    pub inset_color: Rgba,
+    pub background_color: Rgba,
+    pub text_color: Rgba,
+    /// Filled part of the progress bar
+    pub progress_bar_color: Rgba,
+    /// Unfilled part of the progress bar
+    pub progress_track_color: Rgba,
+    pub image_treatment: ImageTreatment,
+}
+
+/// Draws a single tile: its number, name, progress and images
 pub struct TileRenderer<'a> {
     text_renderer: &'a TextRenderer,
 }
@@ -95,48 +328,64 @@ impl<'a> TileRenderer<'a> {
         Self { text_renderer }
     }
 
+    pub fn text_renderer(&self) -> &'a TextRenderer {
+        self.text_renderer
+    }
+
     // TODO: function is chonky, clean it up a bit - does passing options here even make sense?
     pub fn render(&self, tile: &Tile, tile_size: u32, options: &TileRenderOptions) -> Image<Rgba> {
-        let text_color;
-        let mut image;
-        if tile.unlocked {
-            text_color = options.unlocked_theme.text_color;
-            image = render_tile_template(
-                tile_size,
-                options.border_size,
-                options.inset_size,
-                options.unlocked_theme.background_color,
-                options.unlocked_theme.border_color,
-                options.unlocked_theme.inset_color,
-            );
-        } else {
-            text_color = options.locked_theme.text_color;
-            image = render_tile_template(
-                tile_size,
-                options.border_size,
-                options.inset_size,
-                options.locked_theme.background_color,
-                options.locked_theme.border_color,
-                options.locked_theme.inset_color,
-            );
-        }
-        let (x1, mut y1, x2, mut y2) = compute_content_bounds(tile_size, options);
+        let theme = options.themes.get(tile.state);
+        let text_color = theme.text_color;
+        let mut image = render_tile_template(
+            tile_size,
+            options.border_size,
+            options.inset_size,
+            theme.background_color,
+            theme.border_color,
+            theme.inset_color,
+        );
+        // validation rejects tiles this small, but there's nothing to draw in them either way
+        let Some((x1, mut y1, x2, mut y2)) = compute_content_bounds(
+            tile_size,
+            options.border_size,
+            options.inset_size,
+            options.padding,
+        ) else {
+            return image;
+        };
         let text_size = options.text_size as f32;
         // composite in text
         let number_text = self.text_renderer.render(
-            &tile.number.to_string(),
+            tile.number.to_string(),
             &TextRenderOptions {
                 size: text_size,
                 color: text_color,
                 pixelation: None,
             },
         );
-        let name_text = self.text_renderer.render(
-            &tile.name,
+        // hidden tiles shouldn't give away what they are
+        let name = match tile.state {
+            TileState::Hidden => "???",
+            _ => &tile.name,
+        };
+        let content_width = x2 - x1;
+        let name_options = TextRenderOptions {
+            size: text_size,
+            color: text_color,
+            pixelation: None,
+        };
+        let fitted = self.text_renderer.fit(
+            name,
+            content_width,
+            options.max_name_lines,
+            options.min_text_size as f32,
+            &name_options,
+        );
+        let name_text = self.text_renderer.render_lines(
+            &fitted.lines,
             &TextRenderOptions {
-                size: text_size,
-                color: text_color,
-                pixelation: None,
+                size: fitted.size,
+                ..name_options
             },
         );
         image.draw(&Paste {
@@ -145,74 +394,289 @@ impl<'a> TileRenderer<'a> {
             mask: None,
             overlay: Some(OverlayMode::Merge),
         });
-        let content_width = x2 - x1;
-        let x_offset = if name_text.width() < content_width {
-            (content_width - name_text.width()) / 2
-        } else {
-            // TODO: text is too large for the tile - panic here?
-            0
-        };
+        // names that still don't fit are caught by validation, so just let them overflow
+        let x_offset = content_width.saturating_sub(name_text.width()) / 2;
         image.draw(&Paste {
-            position: (x1 + x_offset, y2 - name_text.height()),
+            position: (x1 + x_offset, y2.saturating_sub(name_text.height())),
             image: &name_text,
             mask: None,
             overlay: Some(OverlayMode::Merge),
         });
         // now shift y1 and y2 so that the tile's image does not overlap the text
         y1 += number_text.height() + options.padding;
-        y2 -= name_text.height() + options.padding;
-        let content_width = x2 - x1;
-        let content_height = y2 - y1;
-        let mut item_image = tile.image.clone();
-        // resize image if necessary
-        if item_image.width() > content_width || item_image.height() > content_height {
-            let content_aspect_ratio = content_width as f32 / content_height as f32;
-            let image_aspect_ratio = item_image.width() as f32 / item_image.height() as f32;
-            let scale_factor = if image_aspect_ratio > content_aspect_ratio {
-                // the image is wider relative to its height than the content box is to its height
-                // so the width is the limiting factor
-                content_width as f32 / item_image.width() as f32
-            } else {
-                // otherwise the image is taller relative to its width than the content box is to its width
-                // so the height is the limiting factor
-                content_height as f32 / item_image.height() as f32
+        y2 = y2.saturating_sub(name_text.height() + options.padding);
+        // progress sits between the image and the name, hidden tiles don't show it
+        if let Some(progress) = tile.progress.filter(|_| tile.state != TileState::Hidden) {
+            let row_height =
+                self.render_progress(&mut image, (x1, x2, y2), progress, theme, options);
+            y2 = y2.saturating_sub(row_height + options.padding);
+        }
+        // there might be no room left for the image at all with a long enough name
+        if y2 <= y1 {
+            return image;
+        }
+        if theme.image_treatment == ImageTreatment::Hide {
+            return image;
+        }
+        let image_options = &tile.image_options;
+        let padding = image_options.padding;
+        let content_width = (x2 - x1).saturating_sub(padding.saturating_mul(2));
+        let content_height = (y2 - y1).saturating_sub(padding.saturating_mul(2));
+        // padding can take up all of the room there was
+        if content_width == 0 || content_height == 0 {
+            return image;
+        }
+        let (x1, y1) = (x1 + padding, y1 + padding);
+        let cells = layout_cells(
+            tile.images.len(),
+            (content_width, content_height),
+            image_options.layout,
+            image_options.gap,
+        );
+        // every image gets a cell of the same size, so they come out at the same scale
+        for (item_image, (cell_x, cell_y, cell_width, cell_height)) in tile.images.iter().zip(cells)
+        {
+            let Some(mut item_image) =
+                fit_image(item_image.clone(), (cell_width, cell_height), image_options)
+            else {
+                continue;
             };
-            let new_width = (scale_factor * item_image.width() as f32) as u32;
-            let new_height = (scale_factor * item_image.height() as f32) as u32;
-            item_image.resize(new_width, new_height, ResizeAlgorithm::Bicubic);
-            debug_assert!(
-                item_image.width() <= content_width,
-                "item image too wide after resize"
-            );
-            debug_assert!(
-                item_image.height() <= content_height,
-                "item image too tall after resize"
-            );
-        }
-        // locked tiles are grayed out
-        if !tile.unlocked {
-            desaturate(&mut item_image, 0.9);
-        }
-        let x_pad = (content_width - item_image.width()) / 2;
-        let y_pad = (content_height - item_image.height()) / 2;
+            match theme.image_treatment {
+                ImageTreatment::Desaturate(factor) => desaturate(&mut item_image, factor),
+                ImageTreatment::Darken(factor) => darken(&mut item_image, factor),
+                ImageTreatment::None | ImageTreatment::Hide => {}
+            }
+            let x_pad = (cell_width - item_image.width()) / 2;
+            let y_pad = (cell_height - item_image.height()) / 2;
+            image.draw(&Paste {
+                position: (x1 + cell_x + x_pad, y1 + cell_y + y_pad),
+                image: &item_image,
+                mask: None,
+                overlay: Some(OverlayMode::Merge),
+            });
+        }
+        image
+    }
+}
+
+impl TileRenderer<'_> {
+    /// Draws a progress bar with a "current/target" counter to its right, with the bottom of
+    /// both at `bottom`. Returns the height that was used.
+    fn render_progress(
+        &self,
+        image: &mut Image<Rgba>,
+        (x1, x2, bottom): (u32, u32, u32),
+        progress: ProgressCount,
+        theme: &TileTheme,
+        options: &TileRenderOptions,
+    ) -> u32 {
+        let counter = self.text_renderer.render(
+            progress.to_string(),
+            &TextRenderOptions {
+                size: options.text_size as f32,
+                color: theme.text_color,
+                pixelation: None,
+            },
+        );
+        let row_height = counter.height();
+        let top = bottom.saturating_sub(row_height);
         image.draw(&Paste {
-            position: (x1 + x_pad, y1 + y_pad),
-            image: &item_image,
+            position: (x2.saturating_sub(counter.width()), top),
+            image: &counter,
             mask: None,
             overlay: Some(OverlayMode::Merge),
         });
-        image
+        let bar_end = x2.saturating_sub(counter.width() + options.padding);
+        if bar_end <= x1 {
+            // no room left for the bar, the counter will have to do
+            return row_height;
+        }
+        // at least 4px so that it shows, but never taller than the counter next to it
+        let bar_height = (row_height / 2).max(4).min(row_height);
+        let bar_top = top + (row_height - bar_height) / 2;
+        let track =
+            Rectangle::<Rgba>::from_bounding_box(x1, bar_top, bar_end, bar_top + bar_height)
+                .with_fill(theme.progress_track_color);
+        image.draw(&track);
+        let filled = ((bar_end - x1) as f32 * progress.fraction()).round() as u32;
+        if filled > 0 {
+            let bar = Rectangle::<Rgba>::from_bounding_box(
+                x1,
+                bar_top,
+                x1 + filled,
+                bar_top + bar_height,
+            )
+            .with_fill(theme.progress_bar_color);
+            image.draw(&bar);
+        }
+        row_height
+    }
+}
+
+/// Splits a `width` by `height` area into one (x, y, width, height) cell per image, relative
+/// to the top left of the area. Cells are all the same size and centered in the area.
+fn layout_cells(
+    count: usize,
+    (width, height): (u32, u32),
+    layout: ImageLayout,
+    gap: u32,
+) -> Vec<(u32, u32, u32, u32)> {
+    let n = count as u32;
+    if n == 0 {
+        return Vec::new();
+    }
+    // (cols, rows, cell width, cell height) of a grid with `cols` columns
+    let grid = |cols: u32| {
+        let rows = n.div_ceil(cols);
+        let cell_width = width.saturating_sub(gap.saturating_mul(cols - 1)) / cols;
+        let cell_height = height.saturating_sub(gap.saturating_mul(rows - 1)) / rows;
+        (cols, rows, cell_width, cell_height)
+    };
+    let (cols, rows, cell_width, cell_height) = match layout {
+        ImageLayout::Row => grid(n),
+        // the column count that gives the biggest square cells, fewest columns on a tie
+        ImageLayout::Grid => (1..=n)
+            .rev()
+            .map(grid)
+            .max_by_key(|&(_, _, w, h)| w.min(h))
+            .unwrap_or(grid(n)),
+        ImageLayout::Fan => {
+            // square cells that each step half a cell to the right of the one before
+            let size = height.min(width.saturating_mul(2) / n.saturating_add(1));
+            let step = size / 2;
+            let x = width.saturating_sub(size + step * (n - 1)) / 2;
+            let y = (height - size) / 2;
+            return (0..n).map(|i| (x + i * step, y, size, size)).collect();
+        }
+    };
+    // gaps too big to leave any room for the images
+    if cell_width == 0 || cell_height == 0 {
+        return Vec::new();
+    }
+    let y = height.saturating_sub(rows * cell_height + gap * (rows - 1)) / 2;
+    (0..n)
+        .map(|i| {
+            let (col, row) = (i % cols, i / cols);
+            // the last row might be short, so each row is centered on its own
+            let row_cols = cols.min(n - row * cols);
+            let x = width.saturating_sub(row_cols * cell_width + gap * (row_cols - 1)) / 2;
+            (
+                x + col * (cell_width + gap),
+                y + row * (cell_height + gap),
+                cell_width,
+                cell_height,
+            )
+        })
+        .collect()
+}
+
+/// Trims and scales an image to fit in a `width` by `height` box, returning `None` if the box
+/// is empty. The result is never bigger than the box.
+fn fit_image(
+    mut image: Image<Rgba>,
+    (width, height): (u32, u32),
+    options: &ImageOptions,
+) -> Option<Image<Rgba>> {
+    if options.trim {
+        if let Some((x1, y1, x2, y2)) = opaque_bounds(&image) {
+            image.crop(x1, y1, x2, y2);
+        }
     }
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let scale_x = width as f32 / image.width() as f32;
+    let scale_y = height as f32 / image.height() as f32;
+    let (scale_x, scale_y) = match options.fit {
+        // whichever side is the limiting factor decides the scale for both
+        ImageFit::Contain => (scale_x.min(scale_y), scale_x.min(scale_y)),
+        ImageFit::Cover => (scale_x.max(scale_y), scale_x.max(scale_y)),
+        ImageFit::Fill => (scale_x, scale_y),
+        ImageFit::None => (1.0, 1.0),
+    };
+    let limit = |scale: f32| {
+        let scale = if options.upscale {
+            scale
+        } else {
+            scale.min(1.0)
+        };
+        options.max_scale.map_or(scale, |max| scale.min(max))
+    };
+    let new_width = ((limit(scale_x) * image.width() as f32) as u32).max(1);
+    let new_height = ((limit(scale_y) * image.height() as f32) as u32).max(1);
+    if (new_width, new_height) != image.dimensions() {
+        image.resize(new_width, new_height, options.resize.into());
+    }
+    // cover and none can leave the image bigger than the box, keep the middle of it
+    if image.width() > width || image.height() > height {
+        let x1 = image.width().saturating_sub(width) / 2;
+        let y1 = image.height().saturating_sub(height) / 2;
+        let x2 = x1 + image.width().min(width);
+        let y2 = y1 + image.height().min(height);
+        image.crop(x1, y1, x2, y2);
+    }
+    Some(image)
 }
 
-fn compute_content_bounds(tile_size: u32, options: &TileRenderOptions) -> (u32, u32, u32, u32) {
-    let offset = options.border_size + options.inset_size + options.padding;
+/// The smallest (x1, y1, x2, y2) box holding every pixel that isn't fully transparent, or
+/// `None` if they all are.
+fn opaque_bounds(image: &Image<Rgba>) -> Option<(u32, u32, u32, u32)> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (y, row) in image.pixels().enumerate() {
+        let y = y as u32;
+        let Some(first) = row.iter().position(|p| p.a > 0) else {
+            continue;
+        };
+        // SAFETY: there's at least one opaque pixel in the row
+        let last = row.iter().rposition(|p| p.a > 0).unwrap();
+        let (x1, x2) = (first as u32, last as u32 + 1);
+        bounds = Some(match bounds {
+            Some((bx1, by1, bx2, _)) => (bx1.min(x1), by1, bx2.max(x2), y + 1),
+            None => (x1, y, x2, y + 1),
+        });
+    }
+    bounds
+}
+
+/// The area inside a tile's border, inset and padding, or `None` if they leave no room.
+pub(crate) fn compute_content_bounds(
+    tile_size: u32,
+    border_size: u32,
+    inset_size: u32,
+    padding: u32,
+) -> Option<(u32, u32, u32, u32)> {
+    let offset = border_size
+        .saturating_add(inset_size)
+        .saturating_add(padding);
+    if tile_size <= offset.saturating_mul(2) {
+        return None;
+    }
     let x2 = tile_size - offset;
     let y2 = tile_size - offset;
-    (offset, offset, x2, y2)
+    Some((offset, offset, x2, y2))
+}
+
+/// Pastes `image` onto `canvas` at `position`, cut down to `width` by `height` first.
+pub(crate) fn paste_cropped(
+    canvas: &mut Image<Rgba>,
+    mut image: Image<Rgba>,
+    position: (u32, u32),
+    width: u32,
+    height: u32,
+) {
+    // ril wraps pixels that are pasted past the right edge onto the next row
+    if image.width() > width || image.height() > height {
+        image.crop(0, 0, image.width().min(width), image.height().min(height));
+    }
+    canvas.draw(&Paste {
+        position,
+        image: &image,
+        mask: None,
+        overlay: Some(OverlayMode::Merge),
+    });
 }
 
-fn render_tile_template(
+pub(crate) fn render_tile_template(
     size: u32,
     border_size: u32,
     inset_size: u32,
@@ -226,8 +690,8 @@ fn render_tile_template(
     let inset = Rectangle::<Rgba>::from_bounding_box(
         border_size,
         border_size,
-        size - border_size,
-        size - border_size,
+        size.saturating_sub(border_size),
+        size.saturating_sub(border_size),
     )
     .with_border(Border::new(inset_color, inset_size).with_position(BorderPosition::Inset));
     image.draw(&border);
@@ -247,3 +711,240 @@ fn desaturate(image: &mut Image<Rgba>, factor: f32) {
         p.b = (b + factor * (luma - b)).floor().clamp(0.0, 255.0) as u8;
     });
 }
+
+fn darken(image: &mut Image<Rgba>, factor: f32) {
+    let scale = (1.0 - factor).clamp(0.0, 1.0);
+    image.map_in_place(|_, _, p| {
+        p.r = (p.r as f32 * scale).floor() as u8;
+        p.g = (p.g as f32 * scale).floor() as u8;
+        p.b = (p.b as f32 * scale).floor() as u8;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::TRANSPARENT;
+
+    #[test]
+    fn it_shortens_big_counters() {
+        let count = |current, target| ProgressCount { current, target }.to_string();
+        assert_eq!(count(2, 3), "2/3");
+        assert_eq!(count(9_999, 25_000), "9999/25k");
+        assert_eq!(count(412_345, 1_000_000), "412.3k/1M");
+        assert_eq!(count(1_500_000, 13_034_431), "1.5M/13M");
+    }
+
+    #[test]
+    fn it_leaves_room_inside_tiles() {
+        assert_eq!(compute_content_bounds(100, 2, 3, 5), Some((10, 10, 90, 90)));
+        // borders that meet in the middle leave nothing, and must not underflow
+        assert_eq!(compute_content_bounds(20, 2, 3, 5), None);
+        assert_eq!(compute_content_bounds(8, 2, 3, 5), None);
+        assert_eq!(compute_content_bounds(100, u32::MAX, 0, 1), None);
+    }
+
+    #[test]
+    fn it_fits_images() {
+        let fit = |image: &Image<Rgba>, options: ImageOptions| {
+            fit_image(image.clone(), (200, 100), &options).map(|i| i.dimensions())
+        };
+        let icon = Image::new(32, 32, GREEN);
+        // small images are left alone unless upscaling is on
+        assert_eq!(fit(&icon, ImageOptions::default()), Some((32, 32)));
+        let upscale = ImageOptions {
+            upscale: true,
+            resize: ResizeFilter::Nearest,
+            ..Default::default()
+        };
+        assert_eq!(fit(&icon, upscale), Some((100, 100)));
+        assert_eq!(
+            fit(
+                &icon,
+                ImageOptions {
+                    max_scale: Some(2.0),
+                    ..upscale
+                }
+            ),
+            Some((64, 64))
+        );
+        assert_eq!(
+            fit(
+                &icon,
+                ImageOptions {
+                    fit: ImageFit::Cover,
+                    ..upscale
+                }
+            ),
+            Some((200, 100))
+        );
+        // trimming happens before scaling, so the opaque part fills the space
+        let mut framed = Image::new(32, 32, TRANSPARENT);
+        framed.draw(&Rectangle::<Rgba>::from_bounding_box(8, 12, 24, 20).with_fill(GREEN));
+        assert_eq!(opaque_bounds(&framed), Some((8, 12, 24, 20)));
+        assert_eq!(
+            fit(
+                &framed,
+                ImageOptions {
+                    trim: true,
+                    ..upscale
+                }
+            ),
+            Some((200, 100))
+        );
+    }
+
+    #[test]
+    fn it_pads_images_inside_tiles() {
+        let text_renderer = TextRenderer::default();
+        let tile_renderer = TileRenderer::new(&text_renderer);
+        let mut options = TileRenderOptions::default();
+        options.themes.completed.image_treatment = ImageTreatment::None;
+        // a color nothing else on the tile uses
+        let color = Rgba::new(1, 2, 3, 255);
+        // (x1, y1, x2, y2) of the image once it's drawn on a tile with `padding` around it
+        let image_bounds = |padding| {
+            let tile = Tile {
+                number: 1,
+                name: String::new(),
+                images: vec![Image::new(8, 8, color)],
+                image_options: ImageOptions {
+                    padding,
+                    upscale: true,
+                    resize: ResizeFilter::Nearest,
+                    ..Default::default()
+                },
+                state: TileState::Completed,
+                progress: None,
+            };
+            let image = tile_renderer.render(&tile, 100, &options);
+            let drawn = image
+                .pixels()
+                .enumerate()
+                .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, p)| (x, y, *p)))
+                .filter(|&(_, _, p)| p == color)
+                .map(|(x, y, _)| (x as u32, y as u32))
+                .collect::<Vec<_>>();
+            let xs = drawn.iter().map(|&(x, _)| x);
+            let ys = drawn.iter().map(|&(_, y)| y);
+            Some((xs.clone().min()?, ys.clone().min()?, xs.max()?, ys.max()?))
+        };
+        let (x1, y1, x2, y2) = image_bounds(0).expect("expected the image to be drawn");
+        let (px1, py1, px2, py2) = image_bounds(10).expect("expected the image to be drawn");
+        // the image shrinks by the padding on every side and stays centered
+        assert_eq!((px2 - px1, py2 - py1), (x2 - x1 - 20, y2 - y1 - 20));
+        assert!((px1 - x1).abs_diff(10) <= 1 && (py1 - y1).abs_diff(10) <= 1);
+        // padding that leaves no room means no image
+        assert_eq!(image_bounds(u32::MAX), None);
+    }
+
+    #[test]
+    fn it_lays_out_images() {
+        let cells = |count, layout| layout_cells(count, (200, 100), layout, 4);
+        assert_eq!(cells(1, ImageLayout::Row), vec![(0, 0, 200, 100)]);
+        assert_eq!(
+            cells(3, ImageLayout::Row),
+            vec![(0, 0, 64, 100), (68, 0, 64, 100), (136, 0, 64, 100)]
+        );
+        // four go onto two rows of two, five onto rows of three and two
+        assert_eq!(cells(4, ImageLayout::Grid)[3], (102, 52, 98, 48));
+        assert_eq!(
+            cells(5, ImageLayout::Grid),
+            vec![
+                (0, 0, 64, 48),
+                (68, 0, 64, 48),
+                (136, 0, 64, 48),
+                (34, 52, 64, 48),
+                (102, 52, 64, 48)
+            ]
+        );
+        assert_eq!(
+            cells(3, ImageLayout::Fan),
+            vec![(0, 0, 100, 100), (50, 0, 100, 100), (100, 0, 100, 100)]
+        );
+        // gaps that leave no room for the images don't overflow
+        assert!(layout_cells(3, (200, 100), ImageLayout::Grid, u32::MAX).is_empty());
+    }
+
+    #[test]
+    fn it_draws_each_state_with_its_theme() {
+        let text_renderer = TextRenderer::default();
+        let tile_renderer = TileRenderer::new(&text_renderer);
+        let options = TileRenderOptions::default();
+        let color = Rgba::new(200, 40, 40, 255);
+        // the one color the image comes out as after `treatment`
+        let treated = |treatment| {
+            let mut image = Image::new(1, 1, color);
+            match treatment {
+                ImageTreatment::Desaturate(factor) => desaturate(&mut image, factor),
+                ImageTreatment::Darken(factor) => darken(&mut image, factor),
+                ImageTreatment::None | ImageTreatment::Hide => {}
+            }
+            *image.pixel(0, 0)
+        };
+        for state in [
+            TileState::Hidden,
+            TileState::Revealed,
+            TileState::InProgress,
+            TileState::Completed,
+            TileState::Failed,
+        ] {
+            let tile = Tile {
+                number: 1,
+                name: String::new(),
+                images: vec![Image::new(8, 8, color)],
+                image_options: ImageOptions {
+                    upscale: true,
+                    resize: ResizeFilter::Nearest,
+                    ..Default::default()
+                },
+                state,
+                progress: None,
+            };
+            let image = tile_renderer.render(&tile, 100, &options);
+            let theme = options.themes.get(state);
+            assert_eq!(image.pixel(0, 0), &theme.border_color, "{:?}", state);
+            let drawn = |color| image.pixels().flatten().any(|p| *p == color);
+            let expected = treated(theme.image_treatment);
+            match theme.image_treatment {
+                ImageTreatment::Hide => assert!(!drawn(color) && !drawn(expected)),
+                ImageTreatment::None => assert!(drawn(color)),
+                _ => assert!(drawn(expected) && !drawn(color), "{:?}", state),
+            }
+        }
+        assert_eq!(
+            treated(ImageTreatment::Desaturate(1.0)),
+            Rgba::new(88, 88, 88, 255)
+        );
+        assert_eq!(
+            treated(ImageTreatment::Darken(0.5)),
+            Rgba::new(100, 20, 20, 255)
+        );
+    }
+
+    #[test]
+    fn it_fits_progress_bars_into_tiny_text() {
+        let text_renderer = TextRenderer::default();
+        let tile_renderer = TileRenderer::new(&text_renderer);
+        let mut options = TileRenderOptions::default();
+        let bar_color = options.themes.get(TileState::InProgress).progress_bar_color;
+        for text_size in [1, 2, 4, 7] {
+            // counters this small are shorter than the smallest bar
+            options.text_size = text_size;
+            let tile = Tile {
+                number: 1,
+                name: String::new(),
+                images: vec![Image::new(8, 8, Rgba::black())],
+                image_options: ImageOptions::default(),
+                state: TileState::InProgress,
+                progress: Some(ProgressCount {
+                    current: 1,
+                    target: 2,
+                }),
+            };
+            let image = tile_renderer.render(&tile, 100, &options);
+            let drawn = |color| image.pixels().flatten().any(|p| *p == color);
+            assert!(drawn(bar_color), "text_size {}", text_size);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Looks up item images by name, so that boards can say `item: Serpentine helm` rather than
+//! carrying hand-copied wiki thumbnail URLs.
+
+use std::sync::Arc;
+
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{error::Error, http::HttpClient, sources::ItemImageResolver};
+
+/// The OSRS Wiki's MediaWiki API, which `item`s are looked up against by default
+pub const DEFAULT_WIKI_API_URL: &str = "https://oldschool.runescape.wiki/api.php";
+
+/// Resolves items against a MediaWiki API, going by the OSRS Wiki's naming convention:
+/// `File:<Name>_detail.png` for the detail image, falling back to `File:<Name>.png`, the
+/// inventory icon.
+pub struct WikiResolver {
+    http_client: Arc<HttpClient>,
+    api_url: Url,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiResponse {
+    query: ApiQuery,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiQuery {
+    /// Titles the API rewrote, e.g. underscores to spaces
+    #[serde(default)]
+    normalized: Vec<ApiNormalized>,
+    #[serde(default)]
+    pages: Vec<ApiPage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiNormalized {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiPage {
+    title: String,
+    /// Missing for files that don't exist
+    #[serde(default)]
+    imageinfo: Vec<ApiImageInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiImageInfo {
+    url: String,
+}
+
+impl WikiResolver {
+    pub fn new(http_client: Arc<HttpClient>, api_url: Url) -> Self {
+        Self {
+            http_client,
+            api_url,
+        }
+    }
+}
+
+impl ItemImageResolver for WikiResolver {
+    fn resolve(&self, item: &str) -> Result<Option<String>, Error> {
+        let file_name = item.trim().replace(' ', "_");
+        let candidates = [
+            format!("File:{}_detail.png", file_name),
+            format!("File:{}.png", file_name),
+        ];
+        // both candidates are looked up in one request, then picked from in order
+        let mut url = self.api_url.clone();
+        url.query_pairs_mut()
+            .append_pair("action", "query")
+            .append_pair("format", "json")
+            .append_pair("formatversion", "2")
+            .append_pair("prop", "imageinfo")
+            .append_pair("iiprop", "url")
+            .append_pair("titles", &candidates.join("|"));
+        let bytes = self.http_client.get(&url, HeaderMap::new())?.body;
+        let response = serde_json::from_slice::<ApiResponse>(&bytes)
+            .map_err(|source| Error::WikiResponse { source })?;
+        let url = candidates.iter().find_map(|candidate| {
+            let title = response
+                .query
+                .normalized
+                .iter()
+                .find(|n| &n.from == candidate)
+                .map_or(candidate, |n| &n.to);
+            let page = response.query.pages.iter().find(|p| &p.title == title)?;
+            page.imageinfo.first().map(|info| info.url.clone())
+        });
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+    use crate::http::HttpOptions;
+
+    #[test]
+    fn it_falls_back_to_the_inventory_icon() {
+        // a one-shot fixture server standing in for the wiki, which has no detail image
+        let listener = TcpListener::bind("127.0.0.1:0").expect("expected to bind");
+        let api_url = format!("http://{}/api.php", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("expected a request");
+            let mut request_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .expect("expected a request line");
+            let body = r#"{"batchcomplete":true,"query":{
+                "normalized":[
+                    {"fromencoded":false,"from":"File:Serpentine_helm_detail.png","to":"File:Serpentine helm detail.png"},
+                    {"fromencoded":false,"from":"File:Serpentine_helm.png","to":"File:Serpentine helm.png"}
+                ],
+                "pages":[
+                    {"ns":6,"title":"File:Serpentine helm detail.png","missing":true,"imagerepository":""},
+                    {"pageid":1,"ns":6,"title":"File:Serpentine helm.png","imagerepository":"local",
+                     "imageinfo":[{"url":"https://wiki.test/images/Serpentine_helm.png"}]}
+                ]}}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .expect("expected to respond");
+            request_line
+        });
+        let http_client = HttpClient::new(HttpOptions::default()).unwrap();
+        let resolver = WikiResolver::new(Arc::new(http_client), Url::parse(&api_url).unwrap());
+        let url = resolver
+            .resolve("Serpentine helm")
+            .expect("expected the lookup to succeed");
+        assert_eq!(
+            url.as_deref(),
+            Some("https://wiki.test/images/Serpentine_helm.png")
+        );
+        let request_line = server.join().unwrap();
+        assert!(request_line
+            .contains("titles=File%3ASerpentine_helm_detail.png%7CFile%3ASerpentine_helm.png"));
+    }
+}